@@ -0,0 +1,235 @@
+//! Test cases stored as files.
+//!
+//! A tests directory holds one case per `NAME.in`/`NAME.out` pair, or per `NAME` directory with
+//! an `input.txt` and an `expected.txt`. Options of a case, like its points, go in an optional
+//! sidecar next to it: `NAME.toml`, or `case.toml` inside the directory. It takes the same keys
+//! as a `[[tests]]` table, except for the input and the expected output. A case directory can
+//! also hold the input files of the case in `files/` and its expected output files in
+//! `expected_files/`.
+
+use crate::test::TestCase;
+use anyhow::{Context as _, Result};
+use log::{debug, warn};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Files of one case found in the tests directory.
+struct CaseFiles {
+    name: String,
+    input: PathBuf,
+    expected: PathBuf,
+    sidecar: PathBuf,
+    /// directory of the case, with its `files/` and `expected_files/`
+    dir: Option<PathBuf>,
+}
+
+/// Every file below `dir` by its path relative to `dir`, empty if there is no `dir`.
+fn read_tree(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in WalkDir::new(dir) {
+        let path = entry?.into_path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.strip_prefix(dir)?.to_string_lossy().into_owned();
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("failed to read {path:?}"))?;
+        files.insert(name, content);
+    }
+    Ok(files)
+}
+
+impl CaseFiles {
+    fn load(self) -> Result<TestCase> {
+        let read =
+            |p: &Path| fs::read_to_string(p).with_context(|| format!("failed to read {p:?}"));
+        let options: TestCase = if self.sidecar.is_file() {
+            toml::from_str(&read(&self.sidecar)?)
+                .with_context(|| format!("illegal options in {:?}", self.sidecar))?
+        } else {
+            TestCase::default()
+        };
+        let (mut files, mut expected_files) = (options.files, options.expected_files);
+        if let Some(dir) = &self.dir {
+            files.extend(read_tree(&dir.join("files"))?);
+            expected_files.extend(read_tree(&dir.join("expected_files"))?);
+        }
+        debug!("Loaded test case {} from {:?}", self.name, self.input);
+        Ok(TestCase {
+            name: options.name.or(Some(self.name)),
+            input: read(&self.input)?,
+            expected: read(&self.expected)?,
+            files,
+            expected_files,
+            ..options
+        })
+    }
+}
+
+/// Compares names so that digits are ordered by their value, e.g. `2` before `10`.
+fn natural(a: &str, b: &str) -> Ordering {
+    let chunks = |s: &str| {
+        s.chars().fold(Vec::<String>::new(), |mut acc, c| {
+            match acc.last_mut() {
+                Some(l) if l.starts_with(|el: char| el.is_ascii_digit()) == c.is_ascii_digit() => {
+                    l.push(c);
+                }
+                _ => acc.push(c.into()),
+            }
+            acc
+        })
+    };
+    let (a, b) = (chunks(a), chunks(b));
+    for (x, y) in a.iter().zip(&b) {
+        let ord = match (x.parse::<u128>(), y.parse::<u128>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Loads every case in `dir`, ordered by name.
+pub fn load_dir(dir: &Path) -> Result<Vec<TestCase>> {
+    let mut found = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {dir:?}"))? {
+        let path = entry?.path();
+        let stem = if path.is_dir() {
+            path.file_name()
+        } else {
+            path.file_stem()
+        };
+        let Some(name) = stem.and_then(|el| el.to_str()).map(str::to_owned) else {
+            continue;
+        };
+        if path.is_dir() {
+            let files = CaseFiles {
+                input: path.join("input.txt"),
+                expected: path.join("expected.txt"),
+                sidecar: path.join("case.toml"),
+                dir: Some(path.clone()),
+                name,
+            };
+            if !files.input.is_file() || !files.expected.is_file() {
+                warn!("{path:?} does not contain an input.txt and an expected.txt, skipping.");
+                continue;
+            }
+            found.push(files);
+        } else if path.extension().is_some_and(|el| el == "in") {
+            let expected = path.with_extension("out");
+            if !expected.is_file() {
+                warn!("{path:?} has no expected output in {expected:?}, skipping.");
+                continue;
+            }
+            found.push(CaseFiles {
+                input: path.clone(),
+                expected,
+                sidecar: path.with_extension("toml"),
+                dir: None,
+                name,
+            });
+        }
+    }
+    if found.is_empty() {
+        warn!("No test cases found in {dir:?}!");
+    }
+    found.sort_by(|a, b| natural(&a.name, &b.name));
+    found.into_iter().map(CaseFiles::load).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for `test`, with `files` written below it.
+    fn tree(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("apcs-tester-cases-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(natural("2", "10"), Ordering::Less);
+        assert_eq!(natural("case2", "case10"), Ordering::Less);
+        assert_eq!(natural("a10", "b2"), Ordering::Less);
+        assert_eq!(natural("case", "case1"), Ordering::Less);
+        assert_eq!(natural("7", "7"), Ordering::Equal);
+    }
+
+    #[test]
+    fn pairs_in_numeric_order() {
+        let dir = tree(
+            "pairs",
+            &[
+                ("10.in", "10\n"),
+                ("10.out", "20\n"),
+                ("2.in", "2\n"),
+                ("2.out", "4\n"),
+                ("2.toml", "points = 3\n"),
+                ("orphan.in", "1\n"),
+            ],
+        );
+        let cases = load_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<_> = cases.iter().map(|el| el.name.as_deref()).collect();
+        assert_eq!(names, [Some("2"), Some("10")]);
+        assert_eq!(
+            (cases[0].input.as_str(), cases[0].expected.as_str()),
+            ("2\n", "4\n")
+        );
+        assert_eq!(cases[0].points, 3);
+    }
+
+    #[test]
+    fn case_directory() {
+        let dir = tree(
+            "dir",
+            &[
+                ("sum/input.txt", "data.txt\n"),
+                ("sum/expected.txt", "6\n"),
+                ("sum/case.toml", "name = \"sum of a file\"\npoints = 2\n"),
+                ("sum/files/data.txt", "1 2 3\n"),
+                ("sum/files/more/extra.txt", "4\n"),
+                ("sum/expected_files/out.txt", "6\n"),
+                ("empty/input.txt", "\n"),
+            ],
+        );
+        let cases = load_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(cases.len(), 1);
+        let case = &cases[0];
+        assert_eq!(case.name.as_deref(), Some("sum of a file"));
+        assert_eq!(case.points, 2);
+        assert_eq!(case.input, "data.txt\n");
+        assert_eq!(case.expected, "6\n");
+        assert_eq!(
+            case.files,
+            BTreeMap::from([
+                ("data.txt".to_owned(), "1 2 3\n".to_owned()),
+                ("more/extra.txt".to_owned(), "4\n".to_owned()),
+            ])
+        );
+        assert_eq!(
+            case.expected_files,
+            BTreeMap::from([("out.txt".to_owned(), "6\n".to_owned())])
+        );
+    }
+}
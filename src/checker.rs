@@ -221,11 +221,10 @@ pub mod static_check {
     use crate::executable::Language;
 
     use super::IllegalExpr;
-    pub fn check(path: &PathBuf) -> Result<Vec<IllegalExpr>> {
-        let allowcfg = crate::config::get_config()?.allow.clone();
-        let lang: Language = path.extension().unwrap().to_str().unwrap().into();
+    /// Entries of the `allow` config.
+    pub fn allowed() -> HashSet<Allow> {
         let mut allowed = HashSet::new();
-        for i in allowcfg {
+        for i in &crate::config::CONFIG.allow {
             allowed.insert(match Allow::from_str(i.as_str()).first() {
                 Some(s) => s.clone(),
                 None => {
@@ -234,6 +233,11 @@ pub mod static_check {
                 }
             });
         }
+        allowed
+    }
+    pub fn check(path: &PathBuf) -> Result<Vec<IllegalExpr>> {
+        let lang: Language = path.extension().unwrap().to_str().unwrap().into();
+        let allowed = allowed();
         let prohibited: Vec<Allow> = Allow::iter()
             .filter(|el| return !allowed.contains(el))
             .collect();
@@ -337,7 +341,7 @@ pub mod static_check {
         }
         fn get_prohibited(&self, lang: &Language) -> Vec<&'static str> {
             match lang {
-                Language::Unknown(_) | Language::Guess => return vec![],
+                Language::Unknown(_) | Language::Custom(_) | Language::Guess => return vec![],
                 Language::C => match &self {
                     Self::SystemCall => {
                         return vec![
@@ -0,0 +1,464 @@
+//! Comparison of the output of a program with the expected output.
+//!
+//! Line-based modes normalize every line and diff the results, so the report can point at the
+//! first wrong line of the expected output. Token-based modes compare the whitespace-separated
+//! tokens one by one and report the line of the first wrong token. Pattern-based modes are for
+//! programs with nondeterministic output, like timestamps or the order of a hash set.
+
+use crate::test::WrongLine;
+use anyhow::{Context as _, Result};
+use core::ops::Range;
+use imara_diff::{Algorithm, diff, intern::InternedInput};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How the output of a program is compared with the expected output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Compare {
+    /// every line must match exactly, including the line endings
+    #[default]
+    Exact,
+    /// whitespace at the end of lines and empty lines at the end are ignored
+    TrailingWhitespace,
+    /// all whitespace and empty lines are ignored
+    Whitespace,
+    /// like `TrailingWhitespace`, but ignoring case
+    CaseInsensitive,
+    /// whitespace-separated tokens must match, no matter how they are split into lines
+    Tokens,
+    /// like `Tokens`, but numbers may differ by the absolute or the relative tolerance
+    Float {
+        #[serde(default = "default_tolerance")]
+        abs: f64,
+        #[serde(default = "default_tolerance")]
+        rel: f64,
+    },
+    /// every expected line is a regex the whole output line must match, ignoring trailing
+    /// whitespace
+    Regex,
+    /// the same lines as expected, in any order, ignoring trailing whitespace
+    Unordered,
+    /// the expected output appears somewhere in the output
+    Contains,
+}
+
+const fn default_tolerance() -> f64 {
+    1e-6
+}
+
+impl core::fmt::Display for Compare {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Exact => return write!(f, "exact"),
+            Self::TrailingWhitespace => return write!(f, "ignoring trailing whitespace"),
+            Self::Whitespace => return write!(f, "ignoring whitespace"),
+            Self::CaseInsensitive => return write!(f, "case-insensitive"),
+            Self::Tokens => return write!(f, "tokens"),
+            Self::Float { abs, rel } => return write!(f, "floats, abs {abs:e} rel {rel:e}"),
+            Self::Regex => return write!(f, "regex per line"),
+            Self::Unordered => return write!(f, "unordered lines"),
+            Self::Contains => return write!(f, "contains"),
+        }
+    }
+}
+
+/// Normalized lines, with the index of the line they came from.
+type Lines = Vec<(usize, String)>;
+
+/// Text of normalized lines, to diff them.
+fn join(lines: &Lines) -> String {
+    lines.iter().map(|(_, el)| format!("{el}\n")).collect()
+}
+
+/// Whitespace-separated tokens of `s`, with the index of their line.
+fn tokens(s: &str) -> Vec<(usize, &str)> {
+    s.lines()
+        .enumerate()
+        .flat_map(|(n, l)| l.split_whitespace().map(move |el| (n, el)))
+        .collect()
+}
+
+/// Whether `got` is within `abs` or `rel` of `expected`, if both are numbers.
+fn close(expected: &str, got: &str, abs: f64, rel: f64) -> bool {
+    if expected == got {
+        return true;
+    }
+    let (Ok(e), Ok(g)) = (expected.parse::<f64>(), got.parse::<f64>()) else {
+        return false;
+    };
+    let diff = (e - g).abs();
+    e == g || diff <= abs || diff <= rel * e.abs()
+}
+
+/// Compiles every line of `expected`, anchored to match whole lines.
+fn patterns(expected: &[(usize, String)]) -> Result<Vec<Regex>> {
+    expected
+        .iter()
+        .map(|(n, el)| {
+            Regex::new(&format!("^(?:{el})$"))
+                .with_context(|| format!("illegal regex on line {}", n + 1))
+        })
+        .collect()
+}
+
+impl Compare {
+    /// Checks that `expected` is usable with this mode, i.e. that its regexes compile.
+    pub fn validate(&self, expected: &str) -> Result<()> {
+        if *self == Self::Regex {
+            patterns(&self.normalize(expected))?;
+        }
+        Ok(())
+    }
+    /// Wrong lines of `got`, empty if it matches `expected`.
+    pub fn check(&self, expected: &str, got: &str) -> Vec<WrongLine<usize>> {
+        match *self {
+            Self::Exact => return diff_lines(expected, got),
+            Self::Tokens | Self::Float { .. } => return self.check_tokens(expected, got),
+            Self::Regex => return self.check_regex(expected, got),
+            Self::Unordered => return self.check_unordered(expected, got),
+            Self::Contains => {
+                if got.contains(expected.trim_end()) {
+                    return vec![];
+                }
+                return vec![WrongLine::new(
+                    0..expected.lines().count(),
+                    0..got.lines().count(),
+                    String::new(),
+                )];
+            }
+            Self::TrailingWhitespace | Self::Whitespace | Self::CaseInsensitive => {
+                let (e, g) = (self.normalize(expected), self.normalize(got));
+                // map the lines of the diff back to the lines of the original output.
+                let orig = |lines: &Lines, r: Range<usize>, total: usize| {
+                    let at = |i: usize| lines.get(i).map_or(total, |el| el.0);
+                    if r.is_empty() {
+                        return at(r.start)..at(r.start);
+                    }
+                    at(r.start)..at(r.end - 1) + 1
+                };
+                return diff_lines(&join(&e), &join(&g))
+                    .into_iter()
+                    .map(|el| {
+                        let (after, text) = el.after;
+                        WrongLine::new(
+                            orig(&e, el.before, expected.lines().count()),
+                            orig(&g, after, got.lines().count()),
+                            text,
+                        )
+                    })
+                    .collect();
+            }
+        }
+    }
+    /// Share of `got` that matches `expected`, for partial credit. Counts lines, or tokens for
+    /// the token-based modes, and divides by the longer of the two. `None` for `Contains`.
+    pub fn matched(&self, expected: &str, got: &str) -> Option<f64> {
+        let (same, total) = match *self {
+            Self::Contains => return None,
+            Self::Tokens | Self::Float { .. } => {
+                let (e, g) = (tokens(expected), tokens(got));
+                let same = e
+                    .iter()
+                    .zip(&g)
+                    .filter(|(a, b)| match *self {
+                        Self::Float { abs, rel } => return close(a.1, b.1, abs, rel),
+                        _ => return a.1 == b.1,
+                    })
+                    .count();
+                (same, e.len().max(g.len()))
+            }
+            Self::Regex => {
+                let (e, g) = (self.normalize(expected), self.normalize(got));
+                let same = patterns(&e).map_or(0, |patterns| {
+                    patterns
+                        .iter()
+                        .zip(&g)
+                        .filter(|(re, (_, line))| re.is_match(line))
+                        .count()
+                });
+                (same, e.len().max(g.len()))
+            }
+            Self::Unordered => {
+                let (e, g) = (self.normalize(expected), self.normalize(got));
+                let mut left: HashMap<&str, usize> = HashMap::new();
+                for (_, line) in &e {
+                    *left.entry(line).or_default() += 1;
+                }
+                let same = g
+                    .iter()
+                    .filter(|(_, line)| {
+                        left.get_mut(line.as_str())
+                            .filter(|el| **el > 0)
+                            .map(|el| *el -= 1)
+                            .is_some()
+                    })
+                    .count();
+                (same, e.len().max(g.len()))
+            }
+            Self::Exact => {
+                let (e, g) = (expected.lines().count(), got.lines().count());
+                let removed: usize = diff_lines(expected, got)
+                    .iter()
+                    .map(|el| el.before.len())
+                    .sum();
+                (e.saturating_sub(removed), e.max(g))
+            }
+            Self::TrailingWhitespace | Self::Whitespace | Self::CaseInsensitive => {
+                let (e, g) = (self.normalize(expected), self.normalize(got));
+                let removed: usize = diff_lines(&join(&e), &join(&g))
+                    .iter()
+                    .map(|el| el.before.len())
+                    .sum();
+                (e.len().saturating_sub(removed), e.len().max(g.len()))
+            }
+        };
+        if total == 0 {
+            return Some(1.0);
+        }
+        Some(same as f64 / total as f64)
+    }
+    fn normalize(&self, s: &str) -> Lines {
+        let mut lines: Lines = s
+            .lines()
+            .enumerate()
+            .map(|(n, l)| match self {
+                Self::Whitespace => return (n, l.split_whitespace().collect()),
+                Self::CaseInsensitive => return (n, l.trim_end().to_lowercase()),
+                _ => return (n, l.trim_end().to_owned()),
+            })
+            .collect();
+        if *self == Self::Whitespace {
+            lines.retain(|el| !el.1.is_empty());
+        }
+        while lines.last().is_some_and(|el| el.1.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+    fn check_tokens(&self, expected: &str, got: &str) -> Vec<WrongLine<usize>> {
+        let (e, g) = (tokens(expected), tokens(got));
+        let matches = |a: &str, b: &str| match *self {
+            Self::Float { abs, rel } => return close(a, b, abs, rel),
+            _ => return a == b,
+        };
+        let wrong = e.iter().zip(&g).position(|(a, b)| !matches(a.1, b.1));
+        let Some(i) = wrong.or((e.len() != g.len()).then(|| e.len().min(g.len()))) else {
+            return vec![];
+        };
+        let line = |tokens: &[(usize, &str)], total: &str| {
+            tokens.get(i).map_or(total.lines().count(), |el| el.0)
+        };
+        let (before, after) = (line(&e, expected), line(&g, got));
+        vec![WrongLine::new(
+            before..before + 1,
+            after..after + 1,
+            g.get(i).map_or_else(String::new, |el| el.1.to_owned()),
+        )]
+    }
+    fn check_regex(&self, expected: &str, got: &str) -> Vec<WrongLine<usize>> {
+        let (e, g) = (self.normalize(expected), self.normalize(got));
+        // patterns are validated when the config is loaded.
+        let Ok(patterns) = patterns(&e) else {
+            return vec![WrongLine::new(0..e.len(), 0..g.len(), String::new())];
+        };
+        let wrong = patterns
+            .iter()
+            .zip(&g)
+            .position(|(re, (_, line))| !re.is_match(line));
+        let Some(i) = wrong.or((e.len() != g.len()).then(|| e.len().min(g.len()))) else {
+            return vec![];
+        };
+        let line =
+            |lines: &Lines, total: &str| lines.get(i).map_or(total.lines().count(), |el| el.0);
+        let (before, after) = (line(&e, expected), line(&g, got));
+        vec![WrongLine::new(
+            before..before + 1,
+            after..after + 1,
+            g.get(i).map_or_else(String::new, |el| el.1.clone()),
+        )]
+    }
+    fn check_unordered(&self, expected: &str, got: &str) -> Vec<WrongLine<usize>> {
+        let (e, g) = (self.normalize(expected), self.normalize(got));
+        let mut left: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (n, line) in e.iter().rev() {
+            left.entry(line).or_default().push(*n);
+        }
+        let mut extra = None;
+        for (n, line) in &g {
+            if left.get_mut(line.as_str()).and_then(Vec::pop).is_none() {
+                extra = extra.or(Some((*n, line)));
+            }
+        }
+        // a missing line is reported before an extra one, since it points into the expected output.
+        let missing = left.values().flatten().min();
+        match (missing, extra) {
+            (Some(&n), _) => {
+                let end = got.lines().count();
+                return vec![WrongLine::new(n..n + 1, end..end, String::new())];
+            }
+            (None, Some((n, line))) => {
+                let end = expected.lines().count();
+                return vec![WrongLine::new(end..end, n..n + 1, line.clone())];
+            }
+            (None, None) => return vec![],
+        }
+    }
+}
+
+/// Lines of `got` that differ from `expected`, ignoring line endings and a missing final newline.
+fn diff_lines(expected: &str, got: &str) -> Vec<WrongLine<usize>> {
+    let mut wrong = vec![];
+    let input = InternedInput::new(expected, got);
+    let sink = |before: Range<u32>, after: Range<u32>| {
+        let hunk_after: Vec<_> = input.after[after.start as usize..after.end as usize]
+            .iter()
+            .map(|&line| return input.interner[line])
+            .collect();
+        wrong.push(WrongLine::new(
+            before.start as usize..before.end as usize,
+            after.start as usize..after.end as usize,
+            hunk_after.join("\n"),
+        ));
+    };
+    diff(Algorithm::Histogram, &input, sink);
+    wrong
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(mode: Compare, expected: &str, got: &str) -> bool {
+        mode.check(expected, got).is_empty()
+    }
+
+    #[test]
+    fn exact() {
+        assert!(ok(Compare::Exact, "1\n2\n", "1\n2\n"));
+        assert!(!ok(Compare::Exact, "1\n2\n", "1\n3\n"));
+        assert!(!ok(Compare::Exact, "1\n2\n", "1 \n2\n"));
+        let wrong = Compare::Exact.check("1\n2\n3\n", "1\nx\n3\n");
+        assert_eq!(wrong.len(), 1);
+        assert_eq!(wrong[0].before, 1..2);
+    }
+
+    #[test]
+    fn trailing_newline() {
+        assert!(ok(Compare::Exact, "1\n2\n", "1\n2"));
+        assert!(ok(Compare::Exact, "3", "3\n"));
+        assert!(!ok(Compare::Exact, "1\n2\n", "1\n2\n\n"));
+        assert!(ok(Compare::TrailingWhitespace, "1\n2\n", "1\n2"));
+        assert!(ok(Compare::TrailingWhitespace, "1\n2\n", "1\n2\n\n\n"));
+        assert!(ok(Compare::Tokens, "1\n2\n", "1\n2"));
+    }
+
+    #[test]
+    fn trailing_whitespace() {
+        assert!(ok(Compare::TrailingWhitespace, "a b\nc\n", "a b  \nc\t\n"));
+        assert!(!ok(Compare::TrailingWhitespace, "a b\nc\n", "a  b\nc\n"));
+        assert!(!ok(Compare::TrailingWhitespace, "a\n\nb\n", "a\nb\n"));
+    }
+
+    #[test]
+    fn whitespace() {
+        assert!(ok(Compare::Whitespace, "a b\n\nc\n", "ab\nc\n\n"));
+        assert!(ok(Compare::Whitespace, "a b\n", "  a\tb\n"));
+        assert!(!ok(Compare::Whitespace, "a b\nc\n", "a b c\n"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert!(ok(
+            Compare::CaseInsensitive,
+            "Hello World\n",
+            "hELLO world  \n"
+        ));
+        assert!(!ok(
+            Compare::CaseInsensitive,
+            "Hello World\n",
+            "Hello  World\n"
+        ));
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(ok(Compare::Tokens, "1 2 3\n", "1\n2   3"));
+        assert!(!ok(Compare::Tokens, "1 2 3\n", "1 2\n"));
+        let wrong = Compare::Tokens.check("1\n2\n3\n", "1\n2\n4\n");
+        assert_eq!(wrong[0].before, 2..3);
+        assert_eq!(wrong[0].after.1, "4");
+    }
+
+    #[test]
+    fn float() {
+        let mode = Compare::Float {
+            abs: 1e-3,
+            rel: 1e-6,
+        };
+        assert!(ok(mode, "0.5 x\n", "0.5004 x\n"));
+        assert!(!ok(mode, "0.5 x\n", "0.502 x\n"));
+        assert!(!ok(mode, "0.5 x\n", "0.5 y\n"));
+        // large numbers pass on the relative tolerance even though they are far apart.
+        assert!(ok(mode, "1000000000\n", "1000000900\n"));
+        assert!(!ok(mode, "1000000000\n", "1000002000\n"));
+    }
+
+    #[test]
+    fn float_tolerance_edges() {
+        let abs = Compare::Float { abs: 0.5, rel: 0.0 };
+        assert!(ok(abs, "1.0\n", "1.5\n"));
+        assert!(!ok(abs, "1.0\n", "1.5000001\n"));
+        let rel = Compare::Float { abs: 0.0, rel: 0.5 };
+        assert!(ok(rel, "2\n", "3\n"));
+        assert!(!ok(rel, "2\n", "3.0001\n"));
+        // the relative tolerance is relative to the expected value, so 0 needs an exact match.
+        assert!(!ok(rel, "0\n", "0.0001\n"));
+        assert!(ok(rel, "0\n", "-0.0\n"));
+        assert!(ok(rel, "nan\n", "nan\n"));
+    }
+
+    #[test]
+    fn regex() {
+        assert!(ok(
+            Compare::Regex,
+            "took \\d+ms\n[a-z]+\n",
+            "took 15ms  \nabc\n"
+        ));
+        assert!(!ok(Compare::Regex, "took \\d+ms\n", "it took 15ms\n"));
+        assert!(!ok(Compare::Regex, "a\nb\n", "a\n"));
+        assert!(Compare::Regex.validate("(\n").is_err());
+        assert!(Compare::Exact.validate("(\n").is_ok());
+    }
+
+    #[test]
+    fn unordered() {
+        assert!(ok(Compare::Unordered, "a\nb\nb\n", "b\na\nb\n"));
+        assert!(!ok(Compare::Unordered, "a\nb\nb\n", "b\na\na\n"));
+        let wrong = Compare::Unordered.check("a\nb\n", "b\na\nc\n");
+        assert_eq!(wrong[0].after.0, 2..3);
+    }
+
+    #[test]
+    fn contains() {
+        assert!(ok(
+            Compare::Contains,
+            "answer: 42\n",
+            "thinking\nanswer: 42 (done)\n"
+        ));
+        assert!(!ok(Compare::Contains, "answer: 42\n", "answer: 41\n"));
+        assert_eq!(Compare::Contains.matched("a", "a"), None);
+    }
+
+    #[test]
+    fn matched() {
+        assert_eq!(
+            Compare::Exact.matched("a\nb\nc\nd\n", "a\nb\nx\nd\n"),
+            Some(0.75)
+        );
+        assert_eq!(Compare::Tokens.matched("1 2 3 4", "1 2 3"), Some(0.75));
+        assert_eq!(Compare::Unordered.matched("a\nb\n", "b\nc\n"), Some(0.5));
+        assert_eq!(Compare::Exact.matched("", ""), Some(1.0));
+    }
+}
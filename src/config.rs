@@ -1,5 +1,9 @@
 use crate::checker::{self, Type};
+use crate::compare::Compare;
 use crate::executable::Language;
+use crate::files;
+use crate::interact::Interactor;
+use crate::score::{self, Group};
 use crate::test::TestCase;
 use anyhow::{Ok, Result};
 use clap::{Parser, Subcommand};
@@ -64,9 +68,26 @@ fn load_config() -> Config {
         exit(1);
     }
 
+    let runners = cp.runners.unwrap_or_default();
+    #[cfg(not(target_os = "linux"))]
+    if cp.isolate == Some(true) {
+        warn!("Isolation is only supported on Linux! Running submissions without it.");
+    }
     let config = Config {
+        entry_given: cp.entry.is_some(),
         entry: cp.entry.unwrap_or_else(|| "Main".into()),
-        lang: Language::Guess,
+        lang: cp.lang.as_deref().map_or(Language::Guess, |s| {
+            match Language::from(s.to_lowercase().as_str()) {
+                Language::Unknown(_) if runners.iter().any(|el| el.name == s) => {
+                    Language::Custom(s.to_owned())
+                }
+                Language::Unknown(_) => {
+                    error!("Unknown language \"{s}\"! Falling back to guessing.");
+                    Language::Guess
+                }
+                l => l,
+            }
+        }),
         target: cp
             .target
             .unwrap_or_else(|| std::env::current_dir().unwrap()),
@@ -83,6 +104,7 @@ fn load_config() -> Config {
                         input: a.to_string(),
                         expected: b.to_string(),
                         points: *c,
+                        ..TestCase::default()
                     };
                 }
                 Left((a, b)) => {
@@ -91,19 +113,31 @@ fn load_config() -> Config {
                         input: a.to_string(),
                         expected: b.to_string(),
                         points: 0,
+                        ..TestCase::default()
                     };
                 }
                 Right(c) => {
                     error!("Points without any I/O! Did you forget to add the cases?");
                     TestCase {
-                        input: String::new(),
-                        expected: String::new(),
                         points: *c,
+                        ..TestCase::default()
                     }
                 }
             })
+            .chain(cp.tests.unwrap_or_default())
+            .chain(cp.tests_dir.iter().flat_map(|dir| {
+                crate::cases::load_dir(dir).unwrap_or_else(|e| {
+                    error!("Failed to load test cases from {dir:?}: {e:#}");
+                    exit(1);
+                })
+            }))
+            .enumerate()
+            .map(|(index, el)| TestCase { index, ..el })
             .collect(),
+        compare: cp.compare.unwrap_or_default(),
+        groups: cp.groups.unwrap_or_default(),
         timeout: cp.timeout.unwrap_or(5),
+        cpu_timeout: cp.cpu_timeout,
         memory: cp.memory.unwrap_or(1024),
         #[allow(clippy::unwrap_used)]
         threads: cp.threads.unwrap_or(num_cpus::get().try_into().unwrap()),
@@ -115,7 +149,58 @@ fn load_config() -> Config {
         ),
         orderby: cp.orderby.unwrap_or(Orderby::Id),
         dependencies: cp.dependencies.unwrap_or_default(),
+        toolchain: cp.toolchain.unwrap_or_default(),
+        limits: cp.limits.unwrap_or_default(),
+        isolate: cp.isolate.unwrap_or(false),
+        runners,
     };
+    for (n, case) in config.testcases.iter().enumerate() {
+        let name = case.name.clone().unwrap_or_else(|| (n + 1).to_string());
+        let compare = case.compare.unwrap_or(config.compare);
+        if let Err(e) = compare.validate(&case.expected) {
+            error!("Test case {name} cannot be compared {compare}: {e:#}");
+            exit(1);
+        }
+        if let Some(Err(e)) = case.interactor.as_ref().map(Interactor::validate) {
+            error!("Test case {name} has an illegal interactor: {e:#}");
+            exit(1);
+        }
+        if let Err(e) = files::validate(case) {
+            error!("Test case {name} has an illegal file: {e:#}");
+            exit(1);
+        }
+        let stderr_compare = case.stderr_compare.unwrap_or(compare);
+        if let Some(expected) = &case.stderr
+            && let Err(e) = stderr_compare.validate(expected)
+        {
+            error!("Test case {name} cannot compare stderr {stderr_compare}: {e:#}");
+            exit(1);
+        }
+        if let Some(call) = &case.call {
+            if let Err(e) = call.validate() {
+                error!("Test case {name} has an illegal call: {e:#}");
+                exit(1);
+            }
+            if case.interactor.is_some() || case.judge.is_some() {
+                error!("Test case {name} cannot call a method with an interactor or a checker");
+                exit(1);
+            }
+        }
+        if case.exception.is_some() && case.exit_code.is_some() {
+            error!("Test case {name} cannot expect both an exception and an exit code");
+            exit(1);
+        }
+        for (file, expected) in &case.expected_files {
+            if let Err(e) = compare.validate(expected) {
+                error!("Test case {name} cannot compare {file} {compare}: {e:#}");
+                exit(1);
+            }
+        }
+    }
+    if let Err(e) = score::validate(&config.groups, &config.testcases) {
+        error!("Illegal test groups: {e:#}");
+        exit(1);
+    }
     return config;
 }
 
@@ -158,6 +243,10 @@ impl From<&str> for Language {
             "c" => Self::C,
             "rs" => Self::Rust,
             "py" => Self::Python,
+            "python" => Self::Python,
+            "rust" => Self::Rust,
+            "c++" => Self::Cpp,
+            "guess" => Self::Guess,
             _ => Self::Unknown(String::new()),
         }
     }
@@ -192,7 +281,12 @@ pub struct ConfigParams {
     pub input: Option<Vec<String>>,
     pub output: Option<Vec<String>>,
     pub points: Option<Vec<u64>>,
+    pub tests: Option<Vec<TestCase>>,
+    pub tests_dir: Option<PathBuf>,
+    pub compare: Option<Compare>,
+    pub groups: Option<Vec<Group>>,
     pub timeout: Option<u64>,
+    pub cpu_timeout: Option<u64>,
     pub memory: Option<u64>,
     pub threads: Option<u64>,
     pub checker: Option<Type>,
@@ -200,6 +294,10 @@ pub struct ConfigParams {
     pub format: Option<String>,
     pub orderby: Option<Orderby>,
     pub dependencies: Option<Vec<PathBuf>>,
+    pub toolchain: Option<Toolchain>,
+    pub limits: Option<ResourceLimits>,
+    pub isolate: Option<bool>,
+    pub runners: Option<Vec<RunnerSpec>>,
 }
 
 impl Default for ConfigParams {
@@ -212,7 +310,12 @@ impl Default for ConfigParams {
             input: Some(vec![]),
             output: Some(vec![]),
             points: Some(vec![]),
+            tests: None,
+            tests_dir: None,
+            compare: Some(Compare::Exact),
+            groups: Some(vec![]),
             timeout: Some(10000),
+            cpu_timeout: None,
             memory: None,
             threads: Some(5),
             checker: Some(Type::Static),
@@ -220,19 +323,116 @@ impl Default for ConfigParams {
             allow: Some(vec![]),
             orderby: Some(Orderby::Name),
             dependencies: Some(vec![]),
+            toolchain: Some(Toolchain::default()),
+            limits: Some(ResourceLimits::default()),
+            isolate: Some(false),
+            runners: Some(vec![]),
+        }
+    }
+}
+
+/// External programs used to build and run submissions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Toolchain {
+    /// python interpreter
+    pub python: PathBuf,
+    /// C compiler
+    pub cc: PathBuf,
+    /// C++ compiler
+    pub cxx: PathBuf,
+    /// rust compiler, used for bare `main.rs` submissions
+    pub rustc: PathBuf,
+    /// cargo, used for submissions containing a `Cargo.toml`
+    pub cargo: PathBuf,
+}
+
+impl Default for Toolchain {
+    fn default() -> Self {
+        Self {
+            python: "python3".into(),
+            cc: "gcc".into(),
+            cxx: "g++".into(),
+            rustc: "rustc".into(),
+            cargo: "cargo".into(),
+        }
+    }
+}
+
+/// OS resource limits applied to every run of a submission, on top of `timeout` and `memory`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct ResourceLimits {
+    /// maximum number of processes and threads
+    pub processes: u64,
+    /// maximum number of open file descriptors
+    pub open_files: u64,
+    /// maximum size of a written file, in MB
+    pub file_size: u64,
+    /// maximum size of stdout and of stderr, in KB
+    pub output: u64,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            processes: 512,
+            open_files: 256,
+            file_size: 64,
+            output: 16384,
         }
     }
 }
 
+/// A runner defined entirely in the config, for languages apcs-tester does not know about.
+///
+/// Commands are split on whitespace and run without a shell. The placeholders `{sources}`,
+/// `{entry}`, `{stem}`, `{venv}` and `{out}` are substituted before running.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct RunnerSpec {
+    /// name of the language, usable in `lang`
+    pub name: String,
+    /// extensions of source files, used to detect the language and find the entry point
+    pub extensions: Vec<String>,
+    /// glob of the files substituted into `{sources}`
+    pub sources: Option<String>,
+    /// command that compiles the submission, skipped if absent
+    pub compile: Option<String>,
+    /// command that runs the submission
+    pub run: String,
+    /// whether `RLIMIT_AS` may cap the memory when no cgroup is available, off for runtimes that
+    /// reserve far more address space than they use, like the JVM
+    #[serde(default = "default_address_space")]
+    pub address_space: bool,
+}
+
+const fn default_address_space() -> bool {
+    true
+}
+
 #[derive(Clone, Serialize)]
 #[non_exhaustive]
 pub struct Config {
     pub entry: String,
+    /// whether `entry` was set, rather than falling back to `Main`
+    pub entry_given: bool,
     pub lang: Language,
     pub args: Vec<String>,
     pub target: PathBuf,
     pub testcases: Vec<TestCase>,
+    /// how outputs are compared, unless a case has its own mode
+    pub compare: Compare,
+    /// groups of cases scored together
+    pub groups: Vec<Group>,
+    /// wall-clock time limit in ms
     pub timeout: u64,
+    /// CPU time limit in ms, defaults to a multiple of `timeout`, see [`Limits::for_case`]
+    ///
+    /// [`Limits::for_case`]: crate::lang::sandbox::Limits::for_case
+    pub cpu_timeout: Option<u64>,
     pub memory: u64,
     pub threads: u64,
     pub checker: checker::Type,
@@ -240,6 +440,11 @@ pub struct Config {
     pub format: String,
     pub orderby: Orderby,
     pub dependencies: Vec<PathBuf>,
+    pub toolchain: Toolchain,
+    pub limits: ResourceLimits,
+    /// run submissions in their own namespaces, only supported on Linux
+    pub isolate: bool,
+    pub runners: Vec<RunnerSpec>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -253,11 +458,15 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             entry: String::new(),
+            entry_given: false,
             lang: Language::Guess,
             args: vec![],
             target: env::current_dir().unwrap(),
             testcases: vec![],
+            compare: Compare::Exact,
+            groups: vec![],
             timeout: 10000,
+            cpu_timeout: None,
             memory: 10,
             threads: 5,
             checker: checker::Type::Static,
@@ -265,6 +474,10 @@ impl Default for Config {
             format: "{name}_{num}_{id}_{filename}.{extension}".into(),
             orderby: Orderby::Id,
             dependencies: vec![],
+            toolchain: Toolchain::default(),
+            limits: ResourceLimits::default(),
+            isolate: false,
+            runners: vec![],
         }
     }
 }
@@ -275,10 +488,16 @@ impl Display for Config {
         writeln!(f, "Args: {:?}", self.args)?;
         writeln!(f, "Target: {:?}", self.target)?;
         writeln!(f, "Test Cases: {:?}", self.testcases)?;
+        writeln!(f, "Compare: {}", self.compare)?;
+        writeln!(f, "Groups: {:?}", self.groups)?;
         writeln!(f, "Timeout: {:?}", self.timeout)?;
+        writeln!(f, "CPU Timeout: {:?}", self.cpu_timeout)?;
         writeln!(f, "Memory: {:?}MB", self.memory)?;
         writeln!(f, "Threads: {:?}", self.threads)?;
         writeln!(f, "Checker: {:?}", self.checker)?;
+        writeln!(f, "Toolchain: {:?}", self.toolchain)?;
+        writeln!(f, "Limits: {:?}", self.limits)?;
+        writeln!(f, "Isolate: {:?}", self.isolate)?;
         return writeln!(f, "Allow: {:?}", self.allow);
     }
 }
@@ -607,6 +826,16 @@ pub const KNOWN_EXTENSIONS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
     .into();
 });
 
+/// Whether files with this extension are submissions, counting the config-defined runners.
+#[must_use]
+pub fn is_known_extension(ext: &str) -> bool {
+    KNOWN_EXTENSIONS.contains(ext)
+        || CONFIG
+            .runners
+            .iter()
+            .any(|el| el.extensions.iter().any(|e| e == ext))
+}
+
 // Spinner only properly displays if you have nerd fonts installed.
 pub const SPINNER: [&str; 6] = [
     "\u{ee06}", "\u{ee07}", "\u{ee08}", "\u{ee08}", "\u{ee0a}", "\u{ee0b}",
@@ -12,9 +12,15 @@ impl From<PathBuf> for Language {
             None => {
                 if value.is_dir() {
                     info!("Guessing file type from directory. This may take a while...");
-                    for e in WalkDir::new(&value) {
+                    if value.join("Cargo.toml").is_file() {
+                        return Self::Rust;
+                    }
+                    for e in WalkDir::new(&value)
+                        .into_iter()
+                        .filter(|el| el.as_ref().is_ok_and(|e| e.file_type().is_file()))
+                    {
                         match Self::from(e.unwrap().into_path()) {
-                            Self::Unknown(_) => continue,
+                            Self::Unknown(_) | Self::Custom(_) => continue,
                             l @ (Language::Java
                             | Language::Cpp
                             | Language::C
@@ -80,6 +86,8 @@ pub enum Language {
     C,
     Rust,
     Python,
+    /// language provided by a runner defined in the config
+    Custom(String),
     Unknown(String),
     Guess,
 }
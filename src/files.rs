@@ -0,0 +1,210 @@
+//! Files a test case reads and writes, for programs that do not only use stdin and stdout.
+//!
+//! Before every run, the input files of a case are written into the working directory of the
+//! submission, and stale copies of its expected output files are removed. After the run, the
+//! output files are read back and compared like stdout. Then everything the case created is
+//! removed again and the files it replaced are restored, so one case cannot see what an earlier
+//! one left behind, and files of the submission or its dependencies survive every case.
+
+use crate::test::{TestCase, Verdict};
+use anyhow::{Result, bail};
+use log::debug;
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+use tokio::fs;
+
+/// Contents of the expected output files after a run, `None` for files that were not written.
+pub type Written = BTreeMap<String, Option<String>>;
+
+/// What [`place`] changed in a working directory, so that [`clean`] undoes exactly that.
+#[derive(Debug, Default)]
+pub struct Placed {
+    /// files and directories that did not exist before, parents first
+    created: Vec<PathBuf>,
+    /// files that were overwritten or removed, with what they contained
+    replaced: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl Placed {
+    /// Records `path` and its missing parents up to `dir` as created by the case.
+    fn create(&mut self, dir: &Path, path: &Path) {
+        let mut missing: Vec<_> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|el| *el != dir && !el.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        missing.reverse();
+        missing.push(path.to_path_buf());
+        for path in missing {
+            if !self.created.contains(&path) {
+                self.created.push(path);
+            }
+        }
+    }
+    /// Records what `path` contains before the case changes it, if it exists.
+    async fn replace(&mut self, path: &Path) -> io::Result<()> {
+        match fs::read(path).await {
+            Ok(content) => self.replaced.push((path.to_path_buf(), content)),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Checks that every file of `case` stays inside the working directory.
+pub fn validate(case: &TestCase) -> Result<()> {
+    for name in case.files.keys().chain(case.expected_files.keys()) {
+        let path = Path::new(name);
+        if name.is_empty()
+            || !path
+                .components()
+                .all(|el| matches!(el, Component::Normal(_)))
+        {
+            bail!("{name:?} is not a relative path inside the working directory");
+        }
+    }
+    Ok(())
+}
+
+/// Writes the input files of `case` into `dir` and removes its expected output files, recording
+/// every change in `placed`, also if it fails halfway.
+pub async fn place(dir: &Path, case: &TestCase, placed: &mut Placed) -> io::Result<()> {
+    for name in case.expected_files.keys() {
+        let path = dir.join(name);
+        placed.replace(&path).await?;
+        placed.create(dir, &path);
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    for (name, content) in &case.files {
+        let path = dir.join(name);
+        placed.replace(&path).await?;
+        placed.create(dir, &path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, content).await?;
+    }
+    Ok(())
+}
+
+/// Reads the expected output files of `case` from `dir`.
+pub async fn collect(dir: &Path, case: &TestCase) -> Written {
+    let mut written = Written::new();
+    for name in case.expected_files.keys() {
+        let content = fs::read(dir.join(name))
+            .await
+            .ok()
+            .map(|el| String::from_utf8_lossy(&el).into_owned());
+        written.insert(name.clone(), content);
+    }
+    written
+}
+
+/// Removes what the case created and restores the files it replaced.
+pub async fn clean(placed: &Placed) {
+    for path in placed.created.iter().rev() {
+        let removed = match fs::symlink_metadata(path).await {
+            Ok(meta) if meta.is_dir() => fs::remove_dir(path).await,
+            Ok(_) => fs::remove_file(path).await,
+            Err(e) => Err(e),
+        };
+        // directories the program filled are left alone.
+        if let Err(e) = removed
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            debug!("failed to remove {path:?}: {e}");
+        }
+    }
+    for (path, content) in &placed.replaced {
+        if let Err(e) = fs::write(path, content).await {
+            debug!("failed to restore {path:?}: {e}");
+        }
+    }
+}
+
+/// Verdict on the first output file that does not match, `None` if all of them do.
+pub fn check(case: &TestCase, written: &Written) -> Option<Verdict> {
+    for (name, expected) in &case.expected_files {
+        let Some(Some(got)) = written.get(name) else {
+            return Some(Verdict::WrongAnswer {
+                loc: vec![],
+                file: Some(name.clone()),
+                reason: None,
+            });
+        };
+        let wrong = case.compare().check(expected, got);
+        if !wrong.is_empty() {
+            return Some(Verdict::WrongAnswer {
+                loc: wrong,
+                file: Some(name.clone()),
+                reason: None,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn clean_restores_the_submission() {
+        let dir = std::env::temp_dir().join(format!("apcs-tester-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        for (name, content) in [("Main.java", "class Main {}"), ("data.txt", "shipped")] {
+            fs::write(dir.join(name), content).await.unwrap();
+        }
+        let case = TestCase {
+            files: BTreeMap::from([
+                ("data.txt".to_owned(), "1 2 3\n".to_owned()),
+                ("sub/more.txt".to_owned(), "4\n".to_owned()),
+            ]),
+            expected_files: BTreeMap::from([
+                ("Main.java".to_owned(), String::new()),
+                ("out/sum.txt".to_owned(), "10\n".to_owned()),
+            ]),
+            ..TestCase::default()
+        };
+        let mut placed = Placed::default();
+        place(&dir, &case, &mut placed).await.unwrap();
+        assert!(!dir.join("Main.java").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("sub/more.txt")).await.unwrap(),
+            "4\n"
+        );
+        // what the program writes.
+        fs::create_dir_all(dir.join("out")).await.unwrap();
+        fs::write(dir.join("out/sum.txt"), "10\n").await.unwrap();
+        fs::write(dir.join("Main.java"), "").await.unwrap();
+        assert_eq!(
+            collect(&dir, &case).await["out/sum.txt"].as_deref(),
+            Some("10\n")
+        );
+        clean(&placed).await;
+        let mut left = vec![];
+        let mut entries = fs::read_dir(&dir).await.unwrap();
+        while let Some(el) = entries.next_entry().await.unwrap() {
+            left.push(el.file_name().into_string().unwrap());
+        }
+        left.sort();
+        assert_eq!(left, ["Main.java", "data.txt"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("Main.java")).await.unwrap(),
+            "class Main {}"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("data.txt")).await.unwrap(),
+            "shipped"
+        );
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
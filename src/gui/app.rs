@@ -27,7 +27,12 @@ pub fn launch() {
                 input: serde_json::from_str(&v[4]).ok(),
                 output: serde_json::from_str(&v[5]).ok(),
                 points: serde_json::from_str(&v[6]).ok(),
+                tests: None,
+                tests_dir: None,
+                compare: None,
+                groups: None,
                 timeout: v[7].parse::<u64>().ok(),
+                cpu_timeout: None,
                 memory: v[8].parse().ok(),
                 threads: v[9].parse().ok(),
                 checker: match v[10].as_str() {
@@ -49,6 +54,10 @@ pub fn launch() {
                         .map(|el| PathBuf::from_str(&el).unwrap())
                         .collect(),
                 ),
+                toolchain: None,
+                limits: None,
+                isolate: None,
+                runners: None,
             })
             .is_err()
         {
@@ -0,0 +1,355 @@
+//! Interactive test cases, for programs that alternate between prompting and reading.
+//!
+//! Instead of writing all of the input at once, an interactor talks to the program while it
+//! runs. It is either a script of steps that wait for the program to print something or send it
+//! a line, or an external program whose stdin and stdout are connected to the program's stdout
+//! and stdin. Everything exchanged is recorded in a [`Transcript`].
+
+use crate::config::TEMPDIR;
+use crate::lang::execution::Execution;
+use crate::test::{TestCase, Verdict};
+use anyhow::{Result, bail};
+use core::time::Duration;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    process::Stdio,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    process::Command,
+};
+
+/// How long a step may take, unless the interactor or the step says otherwise.
+const DEFAULT_TIMEOUT: u64 = 1000;
+
+/// Numbers the directories the files of external interactors are written to.
+static RUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Talks to the program of an interactive test case.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Interactor {
+    /// steps run in order
+    pub script: Vec<Step>,
+    /// external interactor, split on whitespace and run without a shell
+    pub command: Option<String>,
+    /// time limit of every step in ms, or of every exchange with an external interactor
+    pub timeout: Option<u64>,
+}
+
+/// One step of a script. Waits for `expect` first, then sends `send`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Step {
+    /// text the program has to print, anywhere after what earlier steps matched
+    pub expect: Option<String>,
+    /// line sent to the program, a newline is added if missing
+    pub send: Option<String>,
+    /// time limit of this step in ms
+    pub timeout: Option<u64>,
+}
+
+/// Who wrote an entry of a [`Transcript`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Side {
+    Program,
+    Interactor,
+}
+
+/// Everything exchanged with an interactive program, in order.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Transcript {
+    pub entries: Vec<(Side, String)>,
+    /// why the interaction failed, if it did
+    pub failure: Option<String>,
+    /// verdict of an external interactor
+    pub verdict: Option<Verdict>,
+    /// bytes of stdout already in the transcript
+    seen: usize,
+}
+
+impl Transcript {
+    fn push(&mut self, side: Side, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let text = String::from_utf8_lossy(data);
+        match self.entries.last_mut() {
+            Some((last, entry)) if *last == side => entry.push_str(&text),
+            _ => self.entries.push((side, text.into_owned())),
+        }
+    }
+    fn fail(&mut self, reason: String) {
+        debug!("interaction failed: {reason}");
+        self.failure.get_or_insert(reason);
+    }
+    /// Adds what the program printed after the interaction ended.
+    pub fn finish(&mut self, stdout: &[u8]) {
+        let rest = stdout.get(self.seen..).unwrap_or_default().to_vec();
+        self.seen = stdout.len();
+        self.push(Side::Program, &rest);
+    }
+}
+
+/// Program output is prefixed with `<`, what the interactor sent with `>`.
+impl core::fmt::Display for Transcript {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // whether the program left its last line open, e.g. a prompt waiting for input.
+        let mut open = false;
+        for (side, text) in &self.entries {
+            let (prefix, text) = match side {
+                Side::Program if open => ("<", text.strip_prefix('\n').unwrap_or(text)),
+                Side::Program => ("<", text.as_str()),
+                Side::Interactor => (">", text.as_str()),
+            };
+            if *side == Side::Program {
+                open = !text.ends_with('\n');
+            }
+            for line in text.lines() {
+                writeln!(f, "{prefix} {line}")?;
+            }
+        }
+        if let Some(failure) = &self.failure {
+            writeln!(f, "! {failure}")?;
+        }
+        return Ok(());
+    }
+}
+
+/// Kills `exec` if it is still running, since the interaction failed.
+fn stop(exec: &mut Execution) {
+    #[cfg(unix)]
+    if exec.running() {
+        let _ = exec.signal(nix::sys::signal::Signal::SIGKILL);
+    }
+}
+
+/// Position of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|el| el == needle)
+}
+
+impl Interactor {
+    /// Checks that exactly one of a script and a command is given.
+    pub fn validate(&self) -> Result<()> {
+        match (self.script.is_empty(), &self.command) {
+            (true, None) => bail!("the interactor needs a script or a command"),
+            (false, Some(_)) => bail!("the interactor cannot have both a script and a command"),
+            _ => Ok(()),
+        }
+    }
+    /// Interacts with `exec`, which has to stay within `wall`. Closes its stdin when done.
+    pub async fn interact(
+        &self,
+        exec: &mut Execution,
+        case: &TestCase,
+        wall: Duration,
+    ) -> Transcript {
+        let mut transcript = Transcript::default();
+        match &self.command {
+            Some(command) => {
+                self.external(command, exec, case, wall, &mut transcript)
+                    .await;
+            }
+            None => self.script(exec, wall, &mut transcript).await,
+        }
+        exec.close_stdin();
+        transcript
+    }
+    /// Time limit of a step, cut short by what is left of `wall`.
+    fn step_timeout(&self, step: Option<u64>, exec: &Execution, wall: Duration) -> Duration {
+        let step = step.or(self.timeout).unwrap_or(DEFAULT_TIMEOUT);
+        Duration::from_millis(step).min(wall.saturating_sub(exec.runtime()))
+    }
+    async fn script(&self, exec: &mut Execution, wall: Duration, transcript: &mut Transcript) {
+        for (n, step) in self.script.iter().enumerate() {
+            let timeout = self.step_timeout(step.timeout, exec, wall);
+            if let Some(expect) = &step.expect {
+                let deadline = tokio::time::Instant::now() + timeout;
+                loop {
+                    let output = exec.stdout_from(transcript.seen);
+                    if let Some(at) = find(&output, expect.as_bytes()) {
+                        let end = at + expect.len();
+                        transcript.push(Side::Program, &output[..end]);
+                        transcript.seen += end;
+                        break;
+                    }
+                    let seen = transcript.seen + output.len();
+                    match tokio::time::timeout_at(deadline, exec.stdout_grown(seen)).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            transcript.fail(format!(
+                                "step {}: the program exited before printing {expect:?}",
+                                n + 1
+                            ));
+                            return;
+                        }
+                        // the wall-clock limit ran out, which is reported as such.
+                        Err(_) if exec.runtime() >= wall => return,
+                        Err(_) => {
+                            transcript.fail(format!(
+                                "step {}: expected {expect:?} within {:.2}s",
+                                n + 1,
+                                timeout.as_secs_f64()
+                            ));
+                            stop(exec);
+                            return;
+                        }
+                    }
+                }
+            }
+            if let Some(send) = &step.send {
+                let mut line = send.clone();
+                if !line.ends_with('\n') {
+                    line.push('\n');
+                }
+                if let Err(e) = exec.send(line.as_bytes()).await {
+                    transcript.fail(format!("step {}: failed to send {send:?}: {e}", n + 1));
+                    return;
+                }
+                transcript.push(Side::Interactor, line.as_bytes());
+            }
+        }
+    }
+    /// Passes data between `exec` and the interactor until the interactor exits, then judges
+    /// by its exit code like a custom checker: 0 accepts and 1 rejects.
+    async fn external(
+        &self,
+        command: &str,
+        exec: &mut Execution,
+        case: &TestCase,
+        wall: Duration,
+        transcript: &mut Transcript,
+    ) {
+        let dir = TEMPDIR
+            .join(".interact")
+            .join(RUNS.fetch_add(1, Ordering::Relaxed).to_string());
+        let verdict = self
+            .proxy(command, &dir, exec, case, wall, transcript)
+            .await
+            .unwrap_or_else(|reason| {
+                info!("interactor failed: {reason}");
+                Verdict::JudgeError { reason }
+            });
+        transcript.verdict = Some(verdict);
+        let _ = fs::remove_dir_all(&dir).await;
+    }
+    async fn proxy(
+        &self,
+        command: &str,
+        dir: &Path,
+        exec: &mut Execution,
+        case: &TestCase,
+        wall: Duration,
+        transcript: &mut Transcript,
+    ) -> Result<Verdict, String> {
+        fs::create_dir_all(dir).await.map_err(|e| e.to_string())?;
+        let mut parts = command.split_whitespace();
+        let Some(program) = parts.next() else {
+            return Err("empty interactor command".into());
+        };
+        let mut interactor = Command::new(program);
+        interactor.args(parts);
+        for (name, content) in [("input", &case.input), ("expected", &case.expected)] {
+            let path = dir.join(name);
+            fs::write(&path, content).await.map_err(|e| e.to_string())?;
+            interactor.arg(path);
+        }
+        let mut child = interactor
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("failed to start interactor: {e}"))?;
+        let mut to = child.stdin.take();
+        let mut from = child.stdout.take().ok_or("interactor has no stdout")?;
+        let mut stderr = child.stderr.take().ok_or("interactor has no stderr")?;
+        // read meanwhile, so an interactor that writes a lot to stderr never blocks on the pipe.
+        let stderr = tokio::spawn(async move {
+            let mut message = String::new();
+            let _ = stderr.read_to_string(&mut message).await;
+            message
+        });
+        let mut buf = [0; 8192];
+        let mut open = (true, true);
+        // the interactor decides, so the exchange is over once it stops talking.
+        while open.1 {
+            let timeout = self.step_timeout(None, exec, wall);
+            let seen = transcript.seen;
+            tokio::select! {
+                grown = exec.stdout_grown(seen), if open.0 => {
+                    let output = exec.stdout_from(seen);
+                    transcript.push(Side::Program, &output);
+                    transcript.seen += output.len();
+                    if let Some(stdin) = &mut to {
+                        if stdin.write_all(&output).await.is_err() {
+                            to = None;
+                        }
+                    }
+                    if !grown {
+                        // the program is done, so the interactor sees EOF.
+                        open.0 = false;
+                        to = None;
+                    }
+                }
+                n = from.read(&mut buf), if open.1 => match n {
+                    Ok(0) | Err(_) => {
+                        open.1 = false;
+                        exec.close_stdin();
+                    }
+                    Ok(n) => {
+                        transcript.push(Side::Interactor, &buf[..n]);
+                        let _ = exec.send(&buf[..n]).await;
+                    }
+                },
+                () = tokio::time::sleep(timeout) => {
+                    if exec.runtime() < wall {
+                        transcript.fail(format!(
+                            "nothing was exchanged within {:.2}s",
+                            timeout.as_secs_f64()
+                        ));
+                        stop(exec);
+                    }
+                    break;
+                }
+            }
+        }
+        drop(to);
+        let timeout = self.step_timeout(None, exec, wall);
+        let status = tokio::time::timeout(timeout, child.wait())
+            .await
+            .map_err(|_| "interactor did not exit".to_owned())?
+            .map_err(|e| e.to_string())?;
+        let message = stderr.await.unwrap_or_default().trim().to_owned();
+        match status.code() {
+            Some(0) => return Ok(Verdict::Accepted),
+            Some(1) => {
+                info!("interactor rejected the program: {message}");
+                stop(exec);
+                transcript.fail(if message.is_empty() {
+                    "rejected by the interactor".into()
+                } else {
+                    message
+                });
+                return Ok(Verdict::WrongAnswer {
+                    loc: vec![],
+                    file: None,
+                    reason: None,
+                });
+            }
+            _ => return Err(format!("interactor exited with {status}: {message}")),
+        }
+    }
+}
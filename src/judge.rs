@@ -0,0 +1,279 @@
+//! Custom checkers, for test cases with more than one valid output.
+//!
+//! A checker is called with the paths of the input, the expected output and the output of the
+//! program. It exits with 0 if the output is correct and 1 if it is wrong. If the first line it
+//! prints is a number between 0 and 1, the case earns that fraction of its points instead, rounded
+//! down. All points take a score of 1 and an exit code of 0, a rejected output earns partial
+//! credit at most. The rest of what it prints is shown as its message.
+
+use crate::config::TEMPDIR;
+use crate::lang::java::JavaRunner;
+use crate::lang::runner::{RunError, Runner as _};
+use crate::lang::sandbox::Limits;
+use crate::test::{TestCase, Verdict};
+use core::time::Duration;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{fs, process::Command, sync::Mutex};
+
+/// A program that decides whether the output of a test case is correct.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Judge {
+    /// external program, split on whitespace and run without a shell
+    Command(String),
+    /// Java source file, compiled once like a submission
+    Java(PathBuf),
+}
+
+impl core::fmt::Display for Judge {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Command(c) => return write!(f, "checked by {c}"),
+            Self::Java(p) => return write!(f, "checked by {}", p.display()),
+        }
+    }
+}
+
+/// Compiled Java checkers by source file, compiled on first use.
+static COMPILED: LazyLock<Mutex<HashMap<PathBuf, Result<JavaRunner, String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Numbers the directories the files of every check are written to.
+static RUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Compiles the checker in `source` into its own directory.
+async fn compile(source: &Path) -> Result<JavaRunner, String> {
+    let Some(file) = source.file_name() else {
+        return Err(format!("{source:?} is not a file"));
+    };
+    let dir = TEMPDIR
+        .join(".judge")
+        .join(format!("{}", RUNS.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&dir).await.map_err(|e| e.to_string())?;
+    fs::copy(source, dir.join(file))
+        .await
+        .map_err(|e| format!("failed to copy {source:?}: {e}"))?;
+    let runner = JavaRunner::new_from_venv(dir.clone(), dir.join(file))
+        .await
+        .map_err(|e| e.to_string())?;
+    match runner.compile_sources().await {
+        Ok(()) => debug!("compiled checker {source:?}"),
+        Err(RunError::CE(_, e) | RunError::RE(_, e)) => {
+            return Err(format!("failed to compile {source:?}: {e}"));
+        }
+    }
+    Ok(runner)
+}
+
+/// Verdict of a checker that exited with 0 if `accepted` and with 1 otherwise, and printed
+/// `score` for a case worth `points`. Only an accepted score of exactly 1 earns all points,
+/// anything less is rounded down to below them.
+fn score_verdict(
+    points: u64,
+    accepted: bool,
+    score: Option<f64>,
+    message: String,
+) -> Result<Verdict, String> {
+    let wrong = Verdict::WrongAnswer {
+        loc: vec![],
+        file: None,
+        reason: None,
+    };
+    let Some(score) = score else {
+        if accepted {
+            return Ok(Verdict::Accepted);
+        }
+        info!("checker rejected the output: {message}");
+        return Ok(wrong);
+    };
+    if !(0.0..=1.0).contains(&score) {
+        return Err(format!(
+            "checker gave a score of {score}, outside of 0 to 1"
+        ));
+    }
+    if accepted && score == 1.0 {
+        return Ok(Verdict::Accepted);
+    }
+    let earned = ((score * points as f64).floor() as u64).min(points.saturating_sub(1));
+    if earned == 0 {
+        return Ok(wrong);
+    }
+    Ok(Verdict::Partial {
+        points: earned,
+        message,
+    })
+}
+
+impl Judge {
+    /// Command that runs the checker, without its arguments.
+    async fn command(&self) -> Result<Command, String> {
+        match self {
+            Self::Command(c) => {
+                let mut parts = c.split_whitespace();
+                let Some(program) = parts.next() else {
+                    return Err("empty checker command".into());
+                };
+                let mut command = Command::new(program);
+                command.args(parts);
+                return Ok(command);
+            }
+            Self::Java(source) => {
+                let mut compiled = COMPILED.lock().await;
+                if !compiled.contains_key(source) {
+                    let runner = compile(source).await;
+                    compiled.insert(source.clone(), runner);
+                }
+                let runner = compiled[source].as_ref().map_err(Clone::clone)?;
+                // checkers are trusted, so they run without a memory limit.
+                let limits = Limits {
+                    memory: None,
+                    ..Limits::from_config()
+                };
+                return runner.command(&limits).map_err(|e| format!("{e:?}"));
+            }
+        }
+    }
+    /// Verdict on `output` of `case`, [`Verdict::JudgeError`] if the checker failed.
+    pub async fn check(&self, case: &TestCase, output: &str) -> Verdict {
+        let dir = TEMPDIR
+            .join(".judge")
+            .join(format!("run-{}", RUNS.fetch_add(1, Ordering::Relaxed)));
+        let verdict = self.run(&dir, case, output).await.unwrap_or_else(|reason| {
+            info!("checker failed: {reason}");
+            Verdict::JudgeError { reason }
+        });
+        let _ = fs::remove_dir_all(&dir).await;
+        verdict
+    }
+    async fn run(&self, dir: &Path, case: &TestCase, output: &str) -> Result<Verdict, String> {
+        fs::create_dir_all(dir).await.map_err(|e| e.to_string())?;
+        let files = [
+            ("input", case.input.as_str()),
+            ("expected", case.expected.as_str()),
+            ("output", output),
+        ];
+        let mut command = self.command().await?;
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::write(&path, content).await.map_err(|e| e.to_string())?;
+            command.arg(path);
+        }
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // the checker gets the same time as the program.
+        let wall = Limits::for_case(case).wall.unwrap_or(Duration::MAX);
+        let out = tokio::time::timeout(wall, command.output())
+            .await
+            .map_err(|_| "checker timed out".to_owned())?
+            .map_err(|e| format!("failed to start checker: {e}"))?;
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let (first, rest) = stdout.split_once('\n').unwrap_or((&stdout, ""));
+        let (score, message) = match first.trim().parse::<f64>() {
+            Ok(score) => (Some(score), rest.trim().to_owned()),
+            Err(_) => (None, stdout.trim().to_owned()),
+        };
+        debug!("checker said {:?}: {message}", out.status);
+        let accepted = match out.status.code() {
+            Some(0) => true,
+            Some(1) => false,
+            _ => {
+                return Err(format!(
+                    "checker exited with {}: {}",
+                    out.status,
+                    String::from_utf8_lossy(&out.stderr).trim()
+                ));
+            }
+        };
+        score_verdict(case.points, accepted, score, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(points: u64, accepted: bool, score: Option<f64>) -> Verdict {
+        score_verdict(points, accepted, score, "msg".to_owned()).unwrap()
+    }
+
+    #[test]
+    fn exit_code_without_score() {
+        assert!(matches!(verdict(5, true, None), Verdict::Accepted));
+        assert!(matches!(
+            verdict(5, false, None),
+            Verdict::WrongAnswer { .. }
+        ));
+        assert!(matches!(verdict(0, true, None), Verdict::Accepted));
+    }
+
+    #[test]
+    fn full_score() {
+        assert!(matches!(verdict(5, true, Some(1.0)), Verdict::Accepted));
+        // a rejected output never earns all points.
+        assert!(matches!(
+            verdict(5, false, Some(1.0)),
+            Verdict::Partial { points: 4, .. }
+        ));
+        assert!(matches!(
+            verdict(1, false, Some(1.0)),
+            Verdict::WrongAnswer { .. }
+        ));
+    }
+
+    #[test]
+    fn rounded_down_below_full_points() {
+        assert!(matches!(
+            verdict(10, true, Some(0.5)),
+            Verdict::Partial { points: 5, .. }
+        ));
+        assert!(matches!(
+            verdict(10, true, Some(0.99)),
+            Verdict::Partial { points: 9, .. }
+        ));
+        assert!(matches!(
+            verdict(3, true, Some(0.999_999)),
+            Verdict::Partial { points: 2, .. }
+        ));
+        assert!(matches!(
+            verdict(10, true, Some(0.05)),
+            Verdict::WrongAnswer { .. }
+        ));
+        assert!(matches!(
+            verdict(10, true, Some(0.0)),
+            Verdict::WrongAnswer { .. }
+        ));
+    }
+
+    #[test]
+    fn zero_points() {
+        assert!(matches!(verdict(0, true, Some(1.0)), Verdict::Accepted));
+        assert!(matches!(
+            verdict(0, true, Some(0.5)),
+            Verdict::WrongAnswer { .. }
+        ));
+        assert!(matches!(
+            verdict(0, false, Some(1.0)),
+            Verdict::WrongAnswer { .. }
+        ));
+    }
+
+    #[test]
+    fn score_out_of_range() {
+        assert!(score_verdict(5, true, Some(1.5), String::new()).is_err());
+        assert!(score_verdict(5, true, Some(-0.1), String::new()).is_err());
+        assert!(score_verdict(5, true, Some(f64::NAN), String::new()).is_err());
+    }
+}
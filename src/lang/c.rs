@@ -0,0 +1,144 @@
+use super::runner::{Error, RunError, Runner};
+use super::sandbox::Limits;
+use crate::config::CONFIG;
+use crate::executable::Language;
+use async_trait::async_trait;
+use log::debug;
+use std::{fs::create_dir_all, path::PathBuf, process::Stdio};
+use tokio::{fs::copy, io::AsyncReadExt as _, process::Command};
+use walkdir::WalkDir;
+
+/// Runner for C and C++ submissions.
+///
+/// Every source file of the entry's language inside the venv is compiled into a single binary,
+/// so helper files are picked up without any extra configuration.
+pub struct CRunner {
+    venv: Option<PathBuf>,
+    entry: PathBuf,
+    binary: PathBuf,
+    lang: Language,
+    deps: Vec<PathBuf>,
+}
+
+/// C++ submissions are handled by the same runner, only with a different compiler.
+pub type CppRunner = CRunner;
+
+impl CRunner {
+    fn compiler(&self) -> &'static PathBuf {
+        match self.lang {
+            Language::Cpp => &CONFIG.toolchain.cxx,
+            _ => &CONFIG.toolchain.cc,
+        }
+    }
+    fn extensions(&self) -> &'static [&'static str] {
+        match self.lang {
+            Language::Cpp => &["cpp", "cc", "cxx"],
+            _ => &["c"],
+        }
+    }
+    fn sources(&self) -> Vec<PathBuf> {
+        WalkDir::new(self.venv.as_ref().unwrap())
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(walkdir::DirEntry::into_path)
+            .filter(|el| {
+                el.extension()
+                    .is_some_and(|e| self.extensions().contains(&e.to_str().unwrap()))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Runner for CRunner {
+    async fn add_dep(&mut self, p: PathBuf) -> Result<(), String> {
+        self.deps.push(p.clone());
+        let target = self
+            .venv
+            .clone()
+            .unwrap()
+            .join(PathBuf::from(p.file_name().unwrap()));
+        copy(p, target).await.map_err(|e| format!("{e}"))?;
+        Ok(())
+    }
+    async fn add_deps(&mut self, p: Vec<PathBuf>) -> Result<(), String> {
+        self.deps.extend(p.clone());
+        let venvdir = self.venv.clone();
+        create_dir_all(venvdir.clone().unwrap()).map_err(|e| format!("{e}"))?;
+        for i in p {
+            let target = venvdir
+                .clone()
+                .unwrap()
+                .join(PathBuf::from(i.file_name().unwrap()));
+            copy(i, target).await.map_err(|e| format!("{e}"))?;
+        }
+        Ok(())
+    }
+
+    async fn prepare(&mut self) -> Result<(), RunError> {
+        let sources = self.sources();
+        debug!("compiling {sources:?} with {:?}", self.compiler());
+        let mut compiler = Command::new(self.compiler())
+            .current_dir(self.venv.clone().unwrap())
+            .args(&CONFIG.args)
+            .args(&sources)
+            .arg("-o")
+            .arg(&self.binary)
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::CE(None, e.to_string()))?;
+        let mut r = String::new();
+        let _ = compiler.stderr.take().unwrap().read_to_string(&mut r).await;
+        match compiler.wait().await {
+            Ok(s) => {
+                if s.code() == Some(0) {
+                    Ok(())
+                } else if s.code().is_none() {
+                    // killed by a signal, e.g. the OOM killer; say so instead of an empty reason.
+                    Err(RunError::CE(
+                        None,
+                        format!("{r}{} was stopped: {s}", self.compiler().display()),
+                    ))
+                } else {
+                    Err(RunError::CE(s.code(), r))
+                }
+            }
+            Err(e) => Err(RunError::CE(None, e.to_string())),
+        }
+    }
+    async fn new_from_venv(venv: PathBuf, entry: PathBuf) -> Result<Self, Error> {
+        let ext: String = entry.extension().unwrap().to_string_lossy().into();
+        let lang = match ext.as_str() {
+            "c" => {
+                debug!("detected C source.");
+                Language::C
+            }
+            "cpp" | "cc" | "cxx" => {
+                debug!("detected C++ source.");
+                Language::Cpp
+            }
+            _ => return Err(Error::new("give me a C or C++ file.")),
+        };
+        Ok(Self {
+            binary: venv.join(entry.file_stem().unwrap()).with_extension("out"),
+            venv: Some(venv),
+            entry,
+            lang,
+            deps: vec![],
+        })
+    }
+    fn command(&self, _limits: &Limits) -> Result<Command, RunError> {
+        if !self.binary.is_file() {
+            return Err(RunError::RE(
+                None,
+                format!("{} hasn't been compiled yet!", self.entry.to_str().unwrap()),
+            ));
+        }
+        let mut command = Command::new(&self.binary);
+        command.current_dir(self.venv.clone().unwrap());
+        Ok(command)
+    }
+    async fn get_lang(&self) -> Language {
+        self.lang.clone()
+    }
+}
@@ -0,0 +1,564 @@
+//! State of a single run of a prepared submission.
+//!
+//! Runners only know how to compile a submission and how to launch it. Every test case spawns a
+//! fresh [`Execution`] from the prepared artifact, so nothing leaks from one case into the next.
+//! Each execution leads its own process group, which is killed as a whole once the run is over.
+//! Its stdout and stderr are read while it runs, so it never blocks on a full pipe, and are cut
+//! off at the output limit. The process is reaped with `wait4`, which reports the CPU time and
+//! peak resident set of it and everything it waited for. If the run has a cgroup, the CPU time is
+//! read from it instead.
+
+#[cfg(target_os = "linux")]
+use super::isolation::Isolation;
+use super::runner::RunError;
+#[cfg(unix)]
+use super::sandbox::Cgroup;
+use super::sandbox::{self, Limits};
+#[cfg(target_os = "linux")]
+use super::seccomp::Seccomp;
+use log::error;
+#[cfg(unix)]
+use nix::{
+    sys::signal::{Signal, killpg},
+    unistd::Pid,
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWriteExt as _},
+    process::{ChildStderr, ChildStdin, ChildStdout, Command},
+    sync::watch,
+    task::JoinHandle,
+};
+
+/// How often the resident set is sampled while waiting for a run.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+/// How long to keep reading output after the process finished, in case something it started
+/// still holds the pipe.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Result of compiling a submission.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Compilation {
+    /// time spent compiling
+    pub time: Duration,
+}
+
+/// Everything read from one stream of a process.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Captured {
+    pub data: Vec<u8>,
+    /// whether the stream went over the output limit and was cut off
+    pub truncated: bool,
+}
+
+impl Captured {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            truncated: false,
+        }
+    }
+    #[must_use]
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+}
+
+/// A stream that is read in the background while the process runs.
+struct Capture {
+    captured: Arc<Mutex<Captured>>,
+    /// number of bytes read so far, closed once the stream is
+    len: watch::Receiver<usize>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    /// Reads `stream` until EOF. Past `limit` bytes, the rest is dropped and the process group
+    /// `pgid` is killed.
+    fn start(
+        mut stream: impl AsyncRead + Unpin + Send + 'static,
+        limit: Option<usize>,
+        pgid: Option<u32>,
+    ) -> Self {
+        let captured = Arc::new(Mutex::new(Captured::default()));
+        let shared = captured.clone();
+        let (grown, len) = watch::channel(0);
+        let task = tokio::spawn(async move {
+            let mut buf = [0; 8192];
+            while let Ok(n) = stream.read(&mut buf).await {
+                if n == 0 {
+                    return;
+                }
+                let mut captured = shared.lock().unwrap();
+                let room = limit.map_or(n, |el| el.saturating_sub(captured.data.len()));
+                captured.data.extend_from_slice(&buf[..n.min(room)]);
+                grown.send_replace(captured.data.len());
+                if n > room {
+                    captured.truncated = true;
+                    #[cfg(unix)]
+                    if let Some(pgid) = pgid {
+                        let _ = killpg(Pid::from_raw(pgid as i32), Signal::SIGKILL);
+                    }
+                    return;
+                }
+            }
+        });
+        Self {
+            captured,
+            len,
+            task: Some(task),
+        }
+    }
+    /// Waits until more than `seen` bytes were read. Returns false if the stream closed first.
+    async fn grown(&mut self, seen: usize) -> bool {
+        loop {
+            if *self.len.borrow_and_update() > seen {
+                return true;
+            }
+            if self.len.changed().await.is_err() {
+                return *self.len.borrow() > seen;
+            }
+        }
+    }
+    /// What was read so far, starting at byte `from`.
+    fn read_from(&self, from: usize) -> Vec<u8> {
+        let captured = self.captured.lock().unwrap();
+        captured.data.get(from..).unwrap_or_default().to_vec()
+    }
+    /// Waits for the stream to close, for at most [`DRAIN_TIMEOUT`], and returns what was read.
+    async fn finish(&mut self) -> Captured {
+        if let Some(mut task) = self.task.take() {
+            if tokio::time::timeout(DRAIN_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                task.abort();
+            }
+        }
+        self.captured.lock().unwrap().clone()
+    }
+    fn truncated(&self) -> bool {
+        self.captured.lock().unwrap().truncated
+    }
+}
+
+/// Resources a finished process used.
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct Usage {
+    /// user and system CPU time
+    pub cpu: Duration,
+    /// peak resident set size in KB
+    pub peak: u64,
+}
+
+/// Waits for `pid` to exit and reaps it, without going through tokio, which would reap it first.
+#[cfg(unix)]
+fn reap(pid: u32) -> io::Result<(ExitStatus, Option<Usage>)> {
+    use std::os::unix::process::ExitStatusExt as _;
+    let mut status = 0;
+    // SAFETY: `rusage` is plain data and both pointers are valid for the call.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: see above.
+    while unsafe { libc::wait4(pid as libc::pid_t, &raw mut status, 0, &raw mut rusage) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    let time = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    let usage = Usage {
+        cpu: time(rusage.ru_utime) + time(rusage.ru_stime),
+        peak: rusage.ru_maxrss as u64,
+    };
+    Ok((ExitStatus::from_raw(status), Some(usage)))
+}
+
+pub struct Execution {
+    /// pid of the process, which is also its process group id
+    pid: Option<u32>,
+    stdin: Option<ChildStdin>,
+    /// reaps the process once it exits
+    reaper: Option<JoinHandle<io::Result<(ExitStatus, Option<Usage>)>>>,
+    start: Instant,
+    end: Option<Instant>,
+    status: Option<ExitStatus>,
+    usage: Option<Usage>,
+    /// highest resident set size seen so far, in KB
+    peak: Option<u64>,
+    limits: Limits,
+    /// whether we killed the process ourselves
+    killed: bool,
+    /// directory the program ran in
+    workdir: Option<PathBuf>,
+    stdout: Option<Capture>,
+    stderr: Option<Capture>,
+    #[cfg(unix)]
+    cgroup: Option<Cgroup>,
+    /// removes the private venv copy once dropped
+    #[cfg(target_os = "linux")]
+    isolation: Option<Isolation>,
+    #[cfg(target_os = "linux")]
+    seccomp: Option<Seccomp>,
+}
+
+impl Execution {
+    /// Spawns `command` with piped stdio, enforcing `limits`.
+    pub fn spawn(mut command: Command, limits: &Limits) -> Result<Self, RunError> {
+        #[cfg(unix)]
+        let cgroup = sandbox::apply(&mut command, limits);
+        #[cfg(target_os = "linux")]
+        let isolation = if limits.isolate {
+            Some(
+                Isolation::apply(&mut command)
+                    .map_err(|e| RunError::RE(None, format!("failed to isolate: {e}")))?,
+            )
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        let workdir = match &isolation {
+            Some(isolation) => Some(isolation.path().to_path_buf()),
+            None => command.as_std().get_current_dir().map(Path::to_path_buf),
+        };
+        #[cfg(not(target_os = "linux"))]
+        let workdir = command.as_std().get_current_dir().map(Path::to_path_buf);
+        // registered last, so an isolated program is filtered but not the process waiting for it.
+        #[cfg(target_os = "linux")]
+        let mut seccomp = if limits.denied.any() {
+            Seccomp::apply(&mut command, limits.denied, workdir.as_deref())
+                .map_err(|e| RunError::RE(None, format!("failed to filter syscalls: {e}")))?
+        } else {
+            None
+        };
+        let mut process = command
+            .as_std_mut()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::RE(None, e.to_string()))?;
+        #[cfg(target_os = "linux")]
+        if let Some(seccomp) = &mut seccomp {
+            seccomp.started();
+        }
+        let start = Instant::now();
+        let pid = process.id();
+        let limit = limits.output_bytes();
+        let stdin = process
+            .stdin
+            .take()
+            .and_then(|el| ChildStdin::from_std(el).ok());
+        let stdout = process
+            .stdout
+            .take()
+            .and_then(|el| ChildStdout::from_std(el).ok())
+            .map(|el| Capture::start(el, limit, Some(pid)));
+        let stderr = process
+            .stderr
+            .take()
+            .and_then(|el| ChildStderr::from_std(el).ok())
+            .map(|el| Capture::start(el, limit, Some(pid)));
+        #[cfg(unix)]
+        let reaper = tokio::task::spawn_blocking(move || reap(pid));
+        #[cfg(not(unix))]
+        let reaper = tokio::task::spawn_blocking(move || process.wait().map(|el| (el, None)));
+        Ok(Self {
+            pid: Some(pid),
+            stdin,
+            reaper: Some(reaper),
+            start,
+            end: None,
+            status: None,
+            usage: None,
+            peak: None,
+            limits: limits.clone(),
+            killed: false,
+            workdir,
+            stdout,
+            stderr,
+            #[cfg(unix)]
+            cgroup,
+            #[cfg(target_os = "linux")]
+            isolation,
+            #[cfg(target_os = "linux")]
+            seccomp,
+        })
+    }
+    /// Writes all of `input` and closes stdin, so programs that read until EOF terminate.
+    pub async fn stdin(&mut self, input: String) -> Result<(), String> {
+        match self.stdin.take() {
+            Some(mut stdin) => stdin
+                .write_all(input.as_bytes())
+                .await
+                .map_err(|e| format!("{e}")),
+            None => Err("Stdin has already been closed!".into()),
+        }
+    }
+    /// Writes `input` without closing stdin, for interactive programs.
+    pub async fn send(&mut self, input: &[u8]) -> Result<(), String> {
+        let Some(stdin) = &mut self.stdin else {
+            return Err("Stdin has already been closed!".into());
+        };
+        stdin.write_all(input).await.map_err(|e| format!("{e}"))?;
+        stdin.flush().await.map_err(|e| format!("{e}"))
+    }
+    /// Closes stdin, so the program sees EOF.
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+    /// Waits until the process wrote more than `seen` bytes to stdout. Returns false if stdout
+    /// was closed first.
+    pub async fn stdout_grown(&mut self, seen: usize) -> bool {
+        match &mut self.stdout {
+            Some(stdout) => stdout.grown(seen).await,
+            None => false,
+        }
+    }
+    /// What the process wrote to stdout so far, starting at byte `from`.
+    pub fn stdout_from(&self, from: usize) -> Vec<u8> {
+        self.stdout
+            .as_ref()
+            .map_or_else(Vec::new, |el| el.read_from(from))
+    }
+    /// Everything the process wrote to stdout, up to the output limit.
+    pub async fn read_all(&mut self) -> Result<Captured, String> {
+        match &mut self.stdout {
+            Some(stdout) => Ok(stdout.finish().await),
+            None => Err("Stdout is not open!".into()),
+        }
+    }
+    /// Everything the process wrote to stderr, up to the output limit.
+    pub async fn read_stderr(&mut self) -> Captured {
+        match &mut self.stderr {
+            Some(stderr) => stderr.finish().await,
+            None => Captured::default(),
+        }
+    }
+    /// The stream that went over the output limit, if any. The process was killed for it.
+    pub fn exceeded_output(&self) -> Option<&'static str> {
+        if self.stdout.as_ref().is_some_and(Capture::truncated) {
+            return Some("stdout");
+        }
+        if self.stderr.as_ref().is_some_and(Capture::truncated) {
+            return Some("stderr");
+        }
+        None
+    }
+    pub fn running(&mut self) -> bool {
+        if self.status.is_some() {
+            return false;
+        }
+        if self.reaper.as_ref().is_some_and(JoinHandle::is_finished) {
+            return false;
+        }
+        self.peak = self.peak.max(self.pid.and_then(resident_peak));
+        true
+    }
+    /// Exit status, once the process has finished and was waited for.
+    pub const fn exitcode(&self) -> Option<ExitStatus> {
+        self.status
+    }
+    /// Waits for the process to finish.
+    pub async fn wait(&mut self) -> Result<ExitStatus, io::Error> {
+        if let Some(s) = self.status {
+            return Ok(s);
+        }
+        let Some(reaper) = self.reaper.take() else {
+            return Err(io::Error::other("process has already been reaped"));
+        };
+        let (s, usage) = reaper.await??;
+        self.usage = usage;
+        self.finish(s);
+        Ok(s)
+    }
+    /// Waits for the process to finish, sampling its memory meanwhile. Once `timeout` runs out,
+    /// the whole process group is killed and reaped. Returns whether it finished in time.
+    pub async fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, io::Error> {
+        if self.status.is_some() {
+            return Ok(true);
+        }
+        let pid = self.pid;
+        let mut peak = self.peak;
+        let mut sample = tokio::time::interval(SAMPLE_INTERVAL);
+        let deadline = tokio::time::sleep(timeout.saturating_sub(self.runtime()));
+        let reaped = {
+            let Some(reaper) = &mut self.reaper else {
+                return Err(io::Error::other("process has already been reaped"));
+            };
+            tokio::pin!(deadline);
+            loop {
+                tokio::select! {
+                    r = &mut *reaper => break Some(r),
+                    () = &mut deadline => break None,
+                    _ = sample.tick() => peak = peak.max(pid.and_then(resident_peak)),
+                }
+            }
+        };
+        self.peak = peak;
+        let Some(reaped) = reaped else {
+            #[cfg(target_os = "linux")]
+            let stopped = self.kill_isolated();
+            #[cfg(not(target_os = "linux"))]
+            let stopped = false;
+            if !stopped {
+                self.kill();
+            }
+            self.wait().await?;
+            return Ok(false);
+        };
+        self.reaper = None;
+        let (s, usage) = reaped??;
+        self.usage = usage;
+        self.finish(s);
+        Ok(true)
+    }
+    /// Records the exit status and kills whatever the process left behind in its group.
+    fn finish(&mut self, s: ExitStatus) {
+        self.end = Some(Instant::now());
+        self.status = Some(s);
+        #[cfg(unix)]
+        if let Some(pid) = self.pid {
+            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+        }
+    }
+    fn kill(&mut self) {
+        #[cfg(unix)]
+        let _ = self.signal(Signal::SIGKILL);
+        #[cfg(not(unix))]
+        error!("killing processes is not supported on this platform!");
+    }
+    /// Kills the program of an isolated run, but not the intermediate process waiting for it, so
+    /// that it reaps the program and `wait4` still reports the CPU time it used. Killing the first
+    /// process of the PID namespace takes everything else in it along. Returns whether there was
+    /// such a program to kill.
+    #[cfg(target_os = "linux")]
+    fn kill_isolated(&mut self) -> bool {
+        let Some(pid) = self
+            .pid
+            .filter(|_| self.isolation.is_some() && self.status.is_none())
+        else {
+            return false;
+        };
+        let children =
+            std::fs::read_to_string(format!("/proc/{pid}/task/{pid}/children")).unwrap_or_default();
+        let mut killed = false;
+        for child in children.split_whitespace().filter_map(|el| el.parse().ok()) {
+            killed |= nix::sys::signal::kill(Pid::from_raw(child), Signal::SIGKILL).is_ok();
+        }
+        self.killed |= killed;
+        killed
+    }
+    /// Whether we killed the process ourselves, e.g. for running out of time.
+    pub const fn killed(&self) -> bool {
+        self.killed
+    }
+    /// Directory the program ran in, with the files it wrote. For isolated runs, this is the
+    /// private copy of the venv, which is removed once the execution is dropped.
+    pub fn workdir(&self) -> Option<&Path> {
+        self.workdir.as_deref()
+    }
+    /// Peak memory of the run in KB, if it could be measured.
+    pub fn peak_memory(&self) -> Option<u64> {
+        #[cfg(unix)]
+        if let Some(peak) = self.cgroup.as_ref().and_then(Cgroup::peak) {
+            return Some(peak / 1024);
+        }
+        self.peak.max(self.usage.map(|el| el.peak))
+    }
+    /// CPU time the process and its children used, once it finished. Taken from the cgroup if
+    /// there is one, since it also counts children that were never waited for.
+    pub fn cpu_time(&self) -> Option<Duration> {
+        #[cfg(unix)]
+        if let Some(cpu) = self.cgroup.as_ref().and_then(Cgroup::cpu_time) {
+            return Some(cpu);
+        }
+        self.usage.map(|el| el.cpu)
+    }
+    /// Sends `s` to the whole process group.
+    #[cfg(unix)]
+    pub fn signal(&mut self, s: Signal) -> Result<(), String> {
+        let Some(id) = self.pid.filter(|_| self.status.is_none()) else {
+            error!("tried to kill PID that does not exist!");
+            return Err("tried to kill PID that does not exist".into());
+        };
+        let pid = Pid::from_raw(id as i32);
+        killpg(pid, s).map_err(|e| {
+            error!("failed to kill process group {pid}! error: {e}");
+            e.to_string()
+        })?;
+        self.killed |= s == Signal::SIGKILL;
+        Ok(())
+    }
+    /// Wall-clock time since the process was spawned, up to when it finished.
+    pub fn runtime(&self) -> Duration {
+        self.end.unwrap_or_else(Instant::now) - self.start
+    }
+    /// The first denied syscall the process made, if any. It was killed for it.
+    pub fn violation(&self) -> Option<String> {
+        #[cfg(target_os = "linux")]
+        return self.seccomp.as_ref().and_then(Seccomp::violation);
+        #[cfg(not(target_os = "linux"))]
+        return None;
+    }
+    /// Whether the finished process was stopped for exceeding its memory limit, judging from the
+    /// cgroup, the error it printed, or a SIGKILL we did not send.
+    pub fn exceeded_memory(&self, stderr: &str) -> bool {
+        if sandbox::reports_oom(stderr) {
+            return true;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt as _;
+            if self.cgroup.as_ref().is_some_and(Cgroup::oom_killed) {
+                return true;
+            }
+            let sigkill = self
+                .status
+                .and_then(|s| s.signal())
+                .is_some_and(|s| s == Signal::SIGKILL as i32);
+            if self.limits.memory.is_some() && sigkill && !self.killed {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Drop for Execution {
+    fn drop(&mut self) {
+        if self.status.is_none() {
+            self.kill();
+        }
+    }
+}
+
+/// Reads the high water mark of the resident set of `pid` and its children from procfs, in KB.
+/// Children count because isolated runs launch the program from an intermediate process.
+fn resident_peak(pid: u32) -> Option<u64> {
+    let hwm = |pid: &str| {
+        std::fs::read_to_string(format!("/proc/{pid}/status"))
+            .ok()?
+            .lines()
+            .find_map(|l| l.strip_prefix("VmHWM:"))
+            .and_then(|el| el.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+    };
+    let children =
+        std::fs::read_to_string(format!("/proc/{pid}/task/{pid}/children")).unwrap_or_default();
+    children
+        .split_whitespace()
+        .filter_map(hwm)
+        .chain(hwm(&pid.to_string()))
+        .max()
+}
@@ -0,0 +1,178 @@
+use super::execution::Execution;
+use super::runner::{Error, RunError, Runner};
+use super::sandbox::Limits;
+use crate::config::{CONFIG, RunnerSpec};
+use crate::executable::Language;
+use crate::test::TestCase;
+use async_trait::async_trait;
+use log::debug;
+use regex::Regex;
+use std::{fs::create_dir_all, path::PathBuf, process::Stdio};
+use tokio::{fs::copy, io::AsyncReadExt as _, process::Command};
+use walkdir::WalkDir;
+
+/// Runner built from a [`RunnerSpec`] in the config.
+pub struct GenericRunner {
+    spec: RunnerSpec,
+    venv: Option<PathBuf>,
+    entry: PathBuf,
+    deps: Vec<PathBuf>,
+}
+
+/// Converts a glob like `src/**/*.kt` into a regex matching paths relative to the venv.
+fn glob_regex(glob: &str) -> Regex {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).unwrap()
+}
+
+impl GenericRunner {
+    pub fn with_spec(spec: RunnerSpec, venv: PathBuf, entry: PathBuf) -> Self {
+        debug!("using config-defined runner {}", spec.name);
+        Self {
+            spec,
+            venv: Some(venv),
+            entry,
+            deps: vec![],
+        }
+    }
+    fn out(&self) -> PathBuf {
+        self.venv.as_ref().unwrap().join(".out")
+    }
+    fn sources(&self) -> Vec<String> {
+        let venv = self.venv.as_ref().unwrap();
+        let Some(glob) = &self.spec.sources else {
+            return vec![self.entry.to_str().unwrap().to_owned()];
+        };
+        let re = glob_regex(glob);
+        WalkDir::new(venv)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|el| el.file_type().is_file())
+            .map(walkdir::DirEntry::into_path)
+            .filter(|el| re.is_match(el.strip_prefix(venv).unwrap().to_str().unwrap()))
+            .map(|el| el.to_str().unwrap().to_owned())
+            .collect()
+    }
+    /// Splits a command template and substitutes the placeholders.
+    fn template(&self, template: &str) -> Result<Command, String> {
+        let mut args = vec![];
+        for word in template.split_whitespace() {
+            if word == "{sources}" {
+                args.extend(self.sources());
+                continue;
+            }
+            args.push(
+                word.replace("{entry}", self.entry.to_str().unwrap())
+                    .replace("{stem}", self.entry.file_stem().unwrap().to_str().unwrap())
+                    .replace("{venv}", self.venv.as_ref().unwrap().to_str().unwrap())
+                    .replace("{out}", self.out().to_str().unwrap()),
+            );
+        }
+        let mut it = args.into_iter();
+        let Some(program) = it.next() else {
+            return Err(format!("runner {} has an empty command!", self.spec.name));
+        };
+        let mut command = Command::new(program);
+        command.args(it).current_dir(self.venv.clone().unwrap());
+        Ok(command)
+    }
+}
+
+#[async_trait]
+impl Runner for GenericRunner {
+    async fn add_dep(&mut self, p: PathBuf) -> Result<(), String> {
+        self.deps.push(p.clone());
+        let target = self
+            .venv
+            .clone()
+            .unwrap()
+            .join(PathBuf::from(p.file_name().unwrap()));
+        copy(p, target).await.map_err(|e| format!("{e}"))?;
+        Ok(())
+    }
+    async fn add_deps(&mut self, p: Vec<PathBuf>) -> Result<(), String> {
+        self.deps.extend(p.clone());
+        let venvdir = self.venv.clone();
+        create_dir_all(venvdir.clone().unwrap()).map_err(|e| format!("{e}"))?;
+        for i in p {
+            let target = venvdir
+                .clone()
+                .unwrap()
+                .join(PathBuf::from(i.file_name().unwrap()));
+            copy(i, target).await.map_err(|e| format!("{e}"))?;
+        }
+        Ok(())
+    }
+
+    async fn prepare(&mut self) -> Result<(), RunError> {
+        create_dir_all(self.out()).map_err(|e| RunError::CE(None, e.to_string()))?;
+        let Some(template) = self.spec.compile.clone() else {
+            debug!("runner {} has no compile step.", self.spec.name);
+            return Ok(());
+        };
+        let mut compiler = self
+            .template(&template)
+            .map_err(|e| RunError::CE(None, e))?
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::CE(None, e.to_string()))?;
+        let mut out = String::new();
+        let mut err = String::new();
+        let mut stdout = compiler.stdout.take().unwrap();
+        let mut stderr = compiler.stderr.take().unwrap();
+        let _ = tokio::join!(
+            stdout.read_to_string(&mut out),
+            stderr.read_to_string(&mut err)
+        );
+        match compiler.wait().await {
+            Ok(s) => {
+                if s.code() == Some(0) {
+                    Ok(())
+                } else {
+                    Err(RunError::CE(s.code(), out + &err))
+                }
+            }
+            Err(e) => Err(RunError::CE(None, e.to_string())),
+        }
+    }
+    /// Picks the configured runner whose extensions match the entry.
+    async fn new_from_venv(venv: PathBuf, entry: PathBuf) -> Result<Self, Error> {
+        let ext = entry.extension().unwrap_or_default().to_str().unwrap();
+        match CONFIG
+            .runners
+            .iter()
+            .find(|el| el.extensions.iter().any(|e| e == ext))
+        {
+            Some(spec) => Ok(Self::with_spec(spec.clone(), venv, entry)),
+            None => Err(Error::new("no configured runner for this file.")),
+        }
+    }
+    fn command(&self, _limits: &Limits) -> Result<Command, RunError> {
+        self.template(&self.spec.run)
+            .map_err(|e| RunError::RE(None, e))
+    }
+    async fn run(&self, limits: &Limits, case: &TestCase) -> Result<Execution, RunError> {
+        let command = self.case_command(limits, case)?;
+        if self.spec.address_space {
+            return Execution::spawn(command, limits);
+        }
+        Execution::spawn(command, &limits.without_address_space())
+    }
+    async fn get_lang(&self) -> Language {
+        Language::Custom(self.spec.name.clone())
+    }
+}
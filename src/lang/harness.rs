@@ -0,0 +1,401 @@
+//! Method-level test cases for Java, e.g. for free-response questions without a `main`.
+//!
+//! Every case with a [`Call`] gets its own generated class `ApcsCaseN`, which constructs the
+//! student's class if needed, calls the method and checks the return value and the state of the
+//! object against Java expressions. The classes are compiled against the submission by
+//! [`JavaRunner::prepare`](super::java::JavaRunner), and a case whose class does not compile,
+//! e.g. because the method is missing, fails on its own. While the call runs, `System.out` is
+//! discarded, so the only output is the report of the harness: one line per check, `PASS` or
+//! `FAIL` with what was expected and what the call gave, and `DONE` once all checks ran. A report
+//! without it was cut short, e.g. by `System.exit`, and is never accepted.
+
+use crate::test::{TestCase, Verdict};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Write as _};
+
+/// Last line of a complete report.
+const DONE: &str = "DONE";
+
+/// Name of the helper class shared by all generated cases.
+pub const HELPER: &str = "ApcsHarness";
+
+/// Source of [`HELPER`], to compare and print values and to read private fields.
+pub const HELPER_SOURCE: &str = r#"import java.io.OutputStream;
+import java.io.PrintStream;
+import java.lang.reflect.Array;
+import java.lang.reflect.Field;
+import java.util.List;
+
+public final class ApcsHarness {
+    private static final double TOLERANCE = 1e-9;
+    private static PrintStream report = System.out;
+
+    public static void start() {
+        report = System.out;
+        System.setOut(new PrintStream(OutputStream.nullOutputStream()));
+    }
+
+    public static void finish() {
+        report.println("DONE");
+        report.flush();
+    }
+
+    public static void check(String label, Object got, Object expected) {
+        if (same(got, expected)) {
+            report.println("PASS\t" + label);
+        } else {
+            report.println("FAIL\t" + label + "\t" + show(got) + "\t" + show(expected));
+        }
+    }
+
+    public static Object field(Object obj, String name) throws ReflectiveOperationException {
+        for (Class<?> c = obj.getClass(); c != null; c = c.getSuperclass()) {
+            try {
+                Field f = c.getDeclaredField(name);
+                f.setAccessible(true);
+                return f.get(obj);
+            } catch (NoSuchFieldException e) {
+                // declared further up, if at all.
+            }
+        }
+        throw new NoSuchFieldException(name);
+    }
+
+    private static boolean isFloat(Number n) {
+        return n instanceof Double || n instanceof Float;
+    }
+
+    private static boolean same(Object a, Object b) {
+        if (a == null || b == null) {
+            return a == b;
+        }
+        if (a instanceof Number && b instanceof Number) {
+            Number x = (Number) a;
+            Number y = (Number) b;
+            if (isFloat(x) || isFloat(y)) {
+                double diff = Math.abs(x.doubleValue() - y.doubleValue());
+                return diff <= TOLERANCE || diff <= TOLERANCE * Math.abs(y.doubleValue());
+            }
+            return x.longValue() == y.longValue();
+        }
+        if (a.getClass().isArray() && b.getClass().isArray()) {
+            int n = Array.getLength(a);
+            if (n != Array.getLength(b)) {
+                return false;
+            }
+            for (int i = 0; i < n; i++) {
+                if (!same(Array.get(a, i), Array.get(b, i))) {
+                    return false;
+                }
+            }
+            return true;
+        }
+        if (a instanceof List && b instanceof List) {
+            List<?> x = (List<?>) a;
+            List<?> y = (List<?>) b;
+            if (x.size() != y.size()) {
+                return false;
+            }
+            for (int i = 0; i < x.size(); i++) {
+                if (!same(x.get(i), y.get(i))) {
+                    return false;
+                }
+            }
+            return true;
+        }
+        return a.equals(b);
+    }
+
+    private static String escape(String s) {
+        return s.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n").replace("\r", "\\r");
+    }
+
+    private static String show(Object o) {
+        if (o == null) {
+            return "null";
+        }
+        if (o instanceof String) {
+            return "\"" + escape((String) o) + "\"";
+        }
+        if (o instanceof Character) {
+            return "'" + escape(o.toString()) + "'";
+        }
+        if (o.getClass().isArray()) {
+            StringBuilder s = new StringBuilder("{");
+            for (int i = 0; i < Array.getLength(o); i++) {
+                s.append(i == 0 ? "" : ", ").append(show(Array.get(o, i)));
+            }
+            return s.append("}").toString();
+        }
+        if (o instanceof List) {
+            StringBuilder s = new StringBuilder("[");
+            for (Object el : (List<?>) o) {
+                s.append(s.length() == 1 ? "" : ", ").append(show(el));
+            }
+            return s.append("]").toString();
+        }
+        return escape(String.valueOf(o));
+    }
+}
+"#;
+
+/// A method call that a test case makes instead of running `main`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Call {
+    /// fully qualified class of the method, defaults to the class of the entry point
+    pub class: Option<String>,
+    /// Java statements run before anything else, e.g. to declare arrays passed to the method
+    pub setup: Option<String>,
+    /// arguments of the constructor as Java expressions. If given, the method is called on the
+    /// new object, which is `obj` in the expressions below. Otherwise it is called statically.
+    pub constructor: Option<Vec<String>>,
+    pub method: String,
+    /// arguments of the method as Java expressions
+    pub args: Vec<String>,
+    /// expected return value as a Java expression
+    pub returns: Option<String>,
+    /// Java expressions checked after the call, with the values they are expected to have
+    pub state: BTreeMap<String, String>,
+}
+
+/// Java string literal of `s`.
+fn literal(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{escaped}\"")
+}
+
+/// Name of the generated class of the case with index `n`.
+pub fn class_name(n: usize) -> String {
+    format!("ApcsCase{}", n + 1)
+}
+
+/// Index of the case of the generated class `name`.
+pub fn case_index(name: &str) -> Option<usize> {
+    name.strip_prefix("ApcsCase")?
+        .parse::<usize>()
+        .ok()?
+        .checked_sub(1)
+}
+
+impl Call {
+    /// Checks that there is a method to call.
+    pub fn validate(&self) -> Result<()> {
+        if self.method.trim().is_empty() {
+            bail!("the call needs a method");
+        }
+        Ok(())
+    }
+    /// Number of checks in the report of a complete run.
+    pub fn checks(&self) -> usize {
+        usize::from(self.returns.is_some()) + self.state.len()
+    }
+    /// Source of the generated class of the case with index `n`, calling a method of `class`
+    /// unless the call names its own.
+    pub fn source(&self, n: usize, class: &str) -> String {
+        let class = self.class.as_deref().unwrap_or(class);
+        let call = format!("{}({})", self.method, self.args.join(", "));
+        let mut body = String::new();
+        if let Some(setup) = self.setup.as_deref() {
+            let _ = writeln!(body, "        {setup}");
+        }
+        let receiver = match self.constructor.as_deref() {
+            Some(args) => {
+                let _ = writeln!(
+                    body,
+                    "        {class} obj = new {class}({});",
+                    args.join(", ")
+                );
+                "obj"
+            }
+            None => class,
+        };
+        match self.returns.as_deref() {
+            Some(expected) => {
+                let _ = writeln!(
+                    body,
+                    "        ApcsHarness.check({}, {receiver}.{call}, {expected});",
+                    literal(&call)
+                );
+            }
+            None => {
+                let _ = writeln!(body, "        {receiver}.{call};");
+            }
+        }
+        for (expr, expected) in &self.state {
+            let _ = writeln!(
+                body,
+                "        ApcsHarness.check({}, {expr}, {expected});",
+                literal(expr)
+            );
+        }
+        format!(
+            "public class {name} {{\n    public static void main(String[] args) throws Throwable {{\n        ApcsHarness.start();\n{body}        ApcsHarness.finish();\n    }}\n}}\n",
+            name = class_name(n)
+        )
+    }
+}
+
+/// Verdict on the report of the harness in `stdout`. A case earns the share of its checks that
+/// passed if it allows partial credit. A case that expects an exception never finishes, so its
+/// report only has to be free of failed checks.
+pub fn check(case: &TestCase, stdout: &str) -> Verdict {
+    let mut passed: u64 = 0;
+    let mut failed: Vec<String> = vec![];
+    let mut done = false;
+    for line in stdout.lines() {
+        if line == DONE {
+            done = true;
+            continue;
+        }
+        let mut parts = line.split('\t');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("PASS"), Some(_), ..) => passed += 1,
+            (Some("FAIL"), Some(label), Some(got), Some(expected)) => {
+                failed.push(format!("{label} is {got}, expected {expected}"));
+            }
+            _ => {}
+        }
+    }
+    let total = passed + failed.len() as u64;
+    let expected = case.call.as_ref().map_or(0, Call::checks) as u64;
+    if case.exception.is_none() && (!done || total != expected) {
+        return Verdict::WrongAnswer {
+            loc: vec![],
+            file: None,
+            reason: Some(format!(
+                "the call did not finish, {total} of {expected} checks ran"
+            )),
+        };
+    }
+    let Some(first) = failed.first() else {
+        return Verdict::Accepted;
+    };
+    // rounded down, so only passing every check earns all points.
+    let points = (case.points * passed / total).min(case.points.saturating_sub(1));
+    if case.partial && points > 0 {
+        return Verdict::Partial {
+            points,
+            message: format!("{passed} of {total} checks passed, {first}"),
+        };
+    }
+    Verdict::WrongAnswer {
+        loc: vec![],
+        file: None,
+        reason: Some(first.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn case(returns: bool, state: usize, partial: bool) -> TestCase {
+        let call = Call {
+            method: "run".to_owned(),
+            returns: returns.then(|| "1".to_owned()),
+            state: (0..state)
+                .map(|el| (format!("obj.x{el}"), el.to_string()))
+                .collect(),
+            ..Call::default()
+        };
+        TestCase {
+            call: Some(call),
+            points: 4,
+            partial,
+            ..TestCase::default()
+        }
+    }
+
+    fn reason(verdict: &Verdict) -> Option<&str> {
+        match *verdict {
+            Verdict::WrongAnswer { ref reason, .. } => reason.as_deref(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn complete_report() {
+        let case = case(true, 1, false);
+        let verdict = check(&case, "PASS\trun()\nPASS\tobj.x0\nDONE\n");
+        assert!(matches!(verdict, Verdict::Accepted));
+        let verdict = check(&case, "PASS\trun()\nFAIL\tobj.x0\t1\t0\nDONE\n");
+        assert_eq!(reason(&verdict), Some("obj.x0 is 1, expected 0"));
+    }
+
+    #[test]
+    fn empty_report() {
+        let verdict = check(&case(true, 0, false), "");
+        assert_eq!(
+            reason(&verdict),
+            Some("the call did not finish, 0 of 1 checks ran")
+        );
+        // a call without checks still has to finish.
+        let verdict = check(&case(false, 0, false), "");
+        assert_eq!(
+            reason(&verdict),
+            Some("the call did not finish, 0 of 0 checks ran")
+        );
+        assert!(matches!(
+            check(&case(false, 0, false), "DONE\n"),
+            Verdict::Accepted
+        ));
+    }
+
+    #[test]
+    fn truncated_report() {
+        let case = case(true, 2, true);
+        let verdict = check(&case, "PASS\trun()\nPASS\tobj.x0\n");
+        assert_eq!(
+            reason(&verdict),
+            Some("the call did not finish, 2 of 3 checks ran")
+        );
+        // a stray marker does not make up for missing checks.
+        let verdict = check(&case, "PASS\trun()\nDONE\n");
+        assert_eq!(
+            reason(&verdict),
+            Some("the call did not finish, 1 of 3 checks ran")
+        );
+    }
+
+    #[test]
+    fn partial_credit() {
+        let report = "PASS\trun()\nPASS\tobj.x0\nFAIL\tobj.x1\t0\t1\nDONE\n";
+        match check(&case(true, 2, true), report) {
+            Verdict::Partial { points, message } => {
+                assert_eq!(points, 2);
+                assert_eq!(message, "2 of 3 checks passed, obj.x1 is 0, expected 1");
+            }
+            other => panic!("expected partial credit, got {other:?}"),
+        }
+        assert!(matches!(
+            check(&case(true, 2, false), report),
+            Verdict::WrongAnswer { .. }
+        ));
+    }
+
+    #[test]
+    fn expected_exception() {
+        let mut case = case(true, 1, false);
+        case.exception = Some("java.lang.IllegalStateException".to_owned());
+        assert!(matches!(check(&case, ""), Verdict::Accepted));
+        assert!(matches!(
+            check(&case, "FAIL\trun()\t2\t1\n"),
+            Verdict::WrongAnswer { .. }
+        ));
+    }
+
+    #[test]
+    fn class_names() {
+        assert_eq!(class_name(0), "ApcsCase1");
+        assert_eq!(case_index(&class_name(41)), Some(41));
+        assert_eq!(case_index("ApcsCase0"), None);
+        assert_eq!(case_index("Main"), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! Namespace isolation for untrusted submissions.
+//!
+//! Every run gets its own user, mount, network, IPC and PID namespaces. The whole file system is
+//! mounted read-only, except for a private writable copy of the venv in `TEMPDIR`, which is
+//! mounted over the venv itself. The network namespace only has an unconfigured loopback device.
+//! Unprivileged user namespaces are enough, so this needs neither root nor containers.
+
+use crate::config::TEMPDIR;
+use log::debug;
+use std::{
+    ffi::{CStr, CString},
+    fs, io,
+    os::unix::{ffi::OsStrExt as _, fs::symlink},
+    path::{Path, PathBuf},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::process::Command;
+use walkdir::WalkDir;
+
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+/// `struct mount_attr` from `linux/mount.h`.
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// Private copy of a venv, removed again when dropped.
+pub struct Isolation {
+    copy: PathBuf,
+}
+
+impl Drop for Isolation {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.copy) {
+            debug!("failed to remove {:?}: {e}", self.copy);
+        }
+    }
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn cstr(p: &Path) -> io::Result<CString> {
+    CString::new(p.as_os_str().as_bytes()).map_err(io::Error::other)
+}
+
+/// Copies `from` to `to`, keeping permissions and symlinks.
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in WalkDir::new(from) {
+        let entry = entry.map_err(io::Error::other)?;
+        let target = to.join(entry.path().strip_prefix(from).unwrap());
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if entry.file_type().is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_file(path: &CStr, content: &CStr) -> io::Result<()> {
+    // SAFETY: both pointers come from valid C strings that outlive the calls.
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+        check(fd)?;
+        let written = libc::write(fd, content.as_ptr().cast(), content.to_bytes().len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn mount_setattr(path: &CStr, attr: &MountAttr) -> io::Result<()> {
+    // SAFETY: `attr` matches the kernel's `struct mount_attr` and its size is passed along.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            libc::AT_RECURSIVE,
+            ptr::from_ref(attr),
+            size_of::<MountAttr>(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Paths and id maps prepared before forking, since the hook must not allocate.
+struct Prepared {
+    venv: CString,
+    copy: CString,
+    uid_map: CString,
+    gid_map: CString,
+}
+
+impl Prepared {
+    /// Runs in the child after fork. Enters the namespaces, sets up the mounts and forks once
+    /// more, so the program becomes PID 1 of its namespace. The intermediate process only waits
+    /// for it and exits the same way, and never returns.
+    fn enter(&self) -> io::Result<()> {
+        // SAFETY: only async-signal-safe calls on pointers prepared before the fork.
+        unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWUSER
+                    | libc::CLONE_NEWNS
+                    | libc::CLONE_NEWNET
+                    | libc::CLONE_NEWIPC
+                    | libc::CLONE_NEWPID,
+            ))?;
+            write_file(c"/proc/self/setgroups", c"deny")?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            check(libc::mount(
+                ptr::null(),
+                c"/".as_ptr(),
+                ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            ))?;
+            check(libc::mount(
+                self.copy.as_ptr(),
+                self.venv.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                ptr::null(),
+            ))?;
+            let readonly = MountAttr {
+                attr_set: MOUNT_ATTR_RDONLY,
+                attr_clr: 0,
+                propagation: 0,
+                userns_fd: 0,
+            };
+            if let Err(e) = mount_setattr(c"/", &readonly) {
+                if e.raw_os_error() != Some(libc::ENOSYS) {
+                    return Err(e);
+                }
+                // kernels before 5.12 can only make the root mount itself read-only.
+                check(libc::mount(
+                    ptr::null(),
+                    c"/".as_ptr(),
+                    ptr::null(),
+                    libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY,
+                    ptr::null(),
+                ))?;
+            } else {
+                let writable = MountAttr {
+                    attr_set: 0,
+                    attr_clr: MOUNT_ATTR_RDONLY,
+                    propagation: 0,
+                    userns_fd: 0,
+                };
+                mount_setattr(&self.venv, &writable)?;
+            }
+            // the working directory still points below the mount.
+            check(libc::chdir(self.venv.as_ptr()))?;
+            let pid = libc::fork();
+            check(pid)?;
+            if pid == 0 {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                let _ = libc::mount(
+                    c"proc".as_ptr(),
+                    c"/proc".as_ptr(),
+                    c"proc".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    ptr::null(),
+                );
+                return Ok(());
+            }
+            // std waits for the exec status pipe to close, so the waiting process must not keep
+            // it, nor any other descriptor, open.
+            if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) < 0 {
+                for fd in 3..1024 {
+                    libc::close(fd);
+                }
+            }
+            let mut status = 0;
+            while libc::waitpid(pid, &raw mut status, 0) < 0 {
+                if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                    libc::_exit(127);
+                }
+            }
+            if libc::WIFSIGNALED(status) {
+                let sig = libc::WTERMSIG(status);
+                libc::signal(sig, libc::SIG_DFL);
+                libc::kill(libc::getpid(), sig);
+                libc::_exit(128 + sig);
+            }
+            libc::_exit(libc::WEXITSTATUS(status));
+        }
+    }
+}
+
+impl Isolation {
+    /// Where the program actually runs, since the copy is mounted over the venv.
+    pub fn path(&self) -> &Path {
+        &self.copy
+    }
+    /// Makes `command` run isolated, with a fresh copy of its working directory.
+    pub fn apply(command: &mut Command) -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let Some(venv) = command.as_std().get_current_dir().map(Path::to_path_buf) else {
+            return Err(io::Error::other("runner did not set a working directory"));
+        };
+        let copy = TEMPDIR.join(".isolated").join(format!(
+            "{}-{}",
+            venv.file_name().unwrap_or_default().to_string_lossy(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let ret = Self { copy };
+        copy_tree(&venv, &ret.copy)?;
+        let (uid, gid) = (nix::unistd::getuid(), nix::unistd::getgid());
+        let prepared = Prepared {
+            venv: cstr(&venv)?,
+            copy: cstr(&ret.copy)?,
+            uid_map: CString::new(format!("{uid} {uid} 1")).unwrap(),
+            gid_map: CString::new(format!("{gid} {gid} 1")).unwrap(),
+        };
+        // SAFETY: see `Prepared::enter`.
+        unsafe {
+            command.pre_exec(move || prepared.enter());
+        }
+        Ok(ret)
+    }
+}
@@ -1,30 +1,288 @@
+use super::execution::Execution;
+use super::harness::{self, HELPER, HELPER_SOURCE};
 use super::runner::{Error, RunError, Runner};
+use super::sandbox::Limits;
+use crate::config::CONFIG;
 use crate::executable::Language;
+use crate::test::TestCase;
 use async_trait::async_trait;
 use log::{debug, warn};
-#[cfg(unix)]
-use nix::sys::signal::{Signal, kill};
+use regex::Regex;
 use std::{
-    fs::create_dir_all,
-    path::PathBuf,
-    process::{ExitStatus, Stdio},
-    sync::OnceLock,
-    time::{Duration, Instant},
-};
-use tokio::{
-    fs::copy,
-    io::{AsyncReadExt as _, AsyncWriteExt as _},
-    process::{Child, ChildStdout, Command},
+    collections::HashMap, fs::create_dir_all, io::Read as _, path::PathBuf, process::Stdio,
+    sync::LazyLock,
 };
+use tokio::{fs::copy, io::AsyncReadExt as _, process::Command};
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+static PACKAGE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^\s*package\s+([\w.]+)\s*;").unwrap());
+
+/// First line of an error of javac, with the class of the file.
+static ERROR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:.*[/\\])?(\w+)\.java:\d+: ").unwrap());
 
 pub struct JavaRunner {
-    start: Option<Instant>,
-    command: Command,
-    process: Option<Child>,
     venv: Option<PathBuf>,
     entry: PathBuf,
     deps: Vec<PathBuf>,
-    exitcode: OnceLock<i32>,
+    /// `.jar` dependencies, put on the classpath
+    classpath: Vec<PathBuf>,
+    /// errors of the generated classes that did not compile, by the index of their case
+    broken: HashMap<usize, String>,
+}
+
+/// Reads `Main-Class` from the manifest of a jar.
+fn manifest_main_class(jar: &PathBuf) -> Option<String> {
+    let mut archive = ZipArchive::new(std::fs::File::open(jar).ok()?).ok()?;
+    let mut manifest = String::new();
+    archive
+        .by_name("META-INF/MANIFEST.MF")
+        .ok()?
+        .read_to_string(&mut manifest)
+        .ok()?;
+    manifest
+        .lines()
+        .find_map(|l| l.strip_prefix("Main-Class:"))
+        .map(|el| el.trim().to_owned())
+}
+
+/// Errors of every generated class in the output of javac, by the index of its case.
+fn blame(errors: &str) -> HashMap<usize, String> {
+    let mut blamed: HashMap<usize, String> = HashMap::new();
+    let mut current = None;
+    for line in errors.lines() {
+        if let Some(c) = ERROR.captures(line) {
+            current = harness::case_index(&c[1]);
+        }
+        if let Some(n) = current {
+            let e = blamed.entry(n).or_default();
+            e.push_str(line);
+            e.push('\n');
+        }
+    }
+    blamed
+}
+
+/// Runs javac, with its errors as a compile error.
+async fn javac(mut command: Command) -> Result<(), RunError> {
+    let mut compiler = command
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| RunError::CE(None, e.to_string()))?;
+    let mut r = String::new();
+    let _ = compiler.stderr.take().unwrap().read_to_string(&mut r).await;
+    match compiler.wait().await {
+        Ok(s) => {
+            if s.code() == Some(0) {
+                Ok(())
+            } else {
+                Err(RunError::CE(s.code(), r))
+            }
+        }
+        Err(e) => Err(RunError::CE(None, e.to_string())),
+    }
+}
+
+/// Whether a jar contains the given fully qualified class.
+fn jar_contains_class(jar: &PathBuf, class: &str) -> bool {
+    std::fs::File::open(jar)
+        .ok()
+        .and_then(|f| ZipArchive::new(f).ok())
+        .is_some_and(|mut el| {
+            el.by_name(&format!("{}.class", class.replace('.', "/")))
+                .is_ok()
+        })
+}
+
+impl JavaRunner {
+    fn is_jar(&self) -> bool {
+        self.entry.extension().is_some_and(|el| el == "jar")
+    }
+    /// Directory the compiled classes are written to, kept apart from the sources.
+    fn out(&self) -> PathBuf {
+        self.venv.as_ref().unwrap().join(".out")
+    }
+    /// Directory the generated classes of method-level cases are written to.
+    fn harness_dir(&self) -> PathBuf {
+        self.venv.as_ref().unwrap().join(".harness")
+    }
+    fn sources(&self) -> Vec<PathBuf> {
+        let (out, harness) = (self.out(), self.harness_dir());
+        WalkDir::new(self.venv.as_ref().unwrap())
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(walkdir::DirEntry::into_path)
+            .filter(|el| !el.starts_with(&out) && !el.starts_with(&harness))
+            .filter(|el| el.extension().is_some_and(|e| e == "java"))
+            .collect()
+    }
+    /// Package of the entry file, read from its `package` declaration.
+    fn package(&self) -> Option<String> {
+        let src = std::fs::read_to_string(&self.entry).ok()?;
+        PACKAGE
+            .captures(&src)
+            .map(|el| el.get(1).unwrap().as_str().to_owned())
+    }
+    /// Fully qualified name of the class to launch.
+    fn main_class(&self) -> String {
+        let stem = self.entry.file_stem().unwrap().to_str().unwrap();
+        self.package()
+            .map_or_else(|| stem.to_owned(), |p| format!("{p}.{stem}"))
+    }
+    /// Directory that contains the root package, derived by stripping the package path off the
+    /// entry's directory. Falls back to the venv if the layout does not match the package.
+    fn source_root(&self) -> PathBuf {
+        let dir = self.entry.parent().unwrap().to_path_buf();
+        let Some(package) = self.package() else {
+            return dir;
+        };
+        let mut root = dir.clone();
+        for part in package.split('.').rev() {
+            if root.file_name().is_some_and(|el| el == part) {
+                root.pop();
+            } else {
+                warn!("{dir:?} does not match package {package}! Using the venv as source root.");
+                return self.venv.clone().unwrap();
+            }
+        }
+        root
+    }
+    /// Class whose methods method-level cases call, unless they name another one.
+    fn default_class(&self) -> String {
+        if self.is_jar() {
+            if self.runs_entry() {
+                return CONFIG.entry.clone();
+            }
+            return manifest_main_class(&self.entry).unwrap_or_else(|| CONFIG.entry.clone());
+        }
+        self.main_class()
+    }
+    /// Whether a jar runs the class `entry` instead of its manifest's `Main-Class`, which takes
+    /// `entry` to be set and inside the jar.
+    fn runs_entry(&self) -> bool {
+        CONFIG.entry_given && jar_contains_class(&self.entry, &CONFIG.entry)
+    }
+    /// Classpath of the generated classes, which are compiled next to the submission.
+    fn harness_classpath(&self) -> String {
+        let mut cp = vec![self.out()];
+        cp.extend(std::env::split_paths(&self.classpath()));
+        std::env::join_paths(cp)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+    /// `java` in the venv, with the memory limit as heap and stack size.
+    fn java(&self, limits: &Limits) -> Command {
+        let mut command = Command::new("java");
+        command.current_dir(self.venv.clone().unwrap());
+        if let Some(memory) = limits.memory {
+            command
+                .arg(format!("-Xmx{memory}m"))
+                .arg(format!("-Xss{}m", (memory / 8).clamp(1, 256)));
+        }
+        command
+    }
+    /// javac writing to the output directory, with the classpath of the submission.
+    fn javac(&self) -> Command {
+        let mut command = Command::new("javac");
+        command
+            .current_dir(self.venv.clone().unwrap())
+            .arg("-d")
+            .arg(self.out())
+            .arg("-cp")
+            .arg(self.classpath());
+        command
+    }
+    /// Compiles the sources of the submission, without the generated classes of method-level
+    /// cases. Jars are already compiled.
+    pub async fn compile_sources(&self) -> Result<(), RunError> {
+        if self.is_jar() {
+            debug!(
+                "Skipping compile for jar file {}",
+                self.entry.to_str().unwrap()
+            );
+            warn!("If this file only contains .java files, this may greatly decrease efficiency.");
+            return Ok(());
+        }
+        let sources = self.sources();
+        debug!(
+            "compiling {sources:?} with source root {:?}",
+            self.source_root()
+        );
+        create_dir_all(self.out()).map_err(|e| RunError::CE(None, e.to_string()))?;
+        let mut command = self.javac();
+        command
+            .arg("-sourcepath")
+            .arg(self.source_root())
+            .args(&sources);
+        javac(command).await
+    }
+    /// Generates and compiles a class for every method-level case. Classes that do not compile,
+    /// e.g. because the method is missing, are left out and their cases fail on their own.
+    async fn compile_harness(&mut self) -> Result<(), RunError> {
+        let calls: Vec<_> = CONFIG
+            .testcases
+            .iter()
+            .filter_map(|el| Some((el.index, el.call.as_ref()?)))
+            .collect();
+        if calls.is_empty() {
+            return Ok(());
+        }
+        let dir = self.harness_dir();
+        let write = |path: PathBuf, source: &str| {
+            std::fs::write(&path, source).map_err(|e| RunError::CE(None, e.to_string()))?;
+            Ok::<_, RunError>(path)
+        };
+        create_dir_all(&dir).map_err(|e| RunError::CE(None, e.to_string()))?;
+        create_dir_all(self.out()).map_err(|e| RunError::CE(None, e.to_string()))?;
+        let helper = write(dir.join(format!("{HELPER}.java")), HELPER_SOURCE)?;
+        let class = self.default_class();
+        let mut pending = vec![];
+        for (n, call) in calls {
+            let path = dir.join(format!("{}.java", harness::class_name(n)));
+            pending.push((n, write(path, &call.source(n, &class))?));
+        }
+        while !pending.is_empty() {
+            let mut command = self.javac();
+            command.arg(&helper).args(pending.iter().map(|el| &el.1));
+            let Err(RunError::CE(_, errors)) = javac(command).await else {
+                break;
+            };
+            let mut blamed = blame(&errors);
+            if !pending.iter().any(|el| blamed.contains_key(&el.0)) {
+                // nothing to leave out, so every case gets all of the errors.
+                for (n, _) in &pending {
+                    self.broken.insert(*n, errors.clone());
+                }
+                break;
+            }
+            pending.retain(|(n, _)| match blamed.remove(n) {
+                Some(e) => {
+                    debug!("{} does not compile:\n{e}", harness::class_name(*n));
+                    self.broken.insert(*n, e);
+                    false
+                }
+                None => true,
+            });
+        }
+        Ok(())
+    }
+    fn classpath(&self) -> String {
+        let mut cp = vec![if self.is_jar() {
+            self.entry.clone()
+        } else {
+            self.out()
+        }];
+        cp.extend(self.classpath.iter().cloned());
+        std::env::join_paths(cp)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
 }
 
 #[async_trait]
@@ -36,188 +294,118 @@ impl Runner for JavaRunner {
             .clone()
             .unwrap()
             .join(PathBuf::from(p.file_name().unwrap()));
-        copy(p, target).await.map_err(|e| format!("{e}"))?;
+        copy(p, &target).await.map_err(|e| format!("{e}"))?;
+        if target.extension().is_some_and(|el| el == "jar") {
+            debug!("adding {target:?} to the classpath.");
+            self.classpath.push(target);
+        }
         Ok(())
     }
     async fn add_deps(&mut self, p: Vec<PathBuf>) -> Result<(), String> {
-        self.deps.extend(p.clone());
-        let venvdir = self.venv.clone();
-        create_dir_all(venvdir.clone().unwrap()).map_err(|e| format!("{e}"))?;
+        create_dir_all(self.venv.clone().unwrap()).map_err(|e| format!("{e}"))?;
         for i in p {
-            let target = venvdir
-                .clone()
-                .unwrap()
-                .join(PathBuf::from(i.file_name().unwrap()));
-            copy(i, target).await.map_err(|e| format!("{e}"))?;
+            self.add_dep(i).await?;
         }
         Ok(())
     }
 
     async fn prepare(&mut self) -> Result<(), RunError> {
-        if self.entry.extension().unwrap().to_str().unwrap() == "jar" {
-            debug!(
-                "Skipping compile for jar file {}",
-                self.entry.to_str().unwrap()
-            );
-            warn!("If this file only contains .java files, this may greatly decrease efficiency.");
-            Ok(())
-        } else {
-            let mut compiler = Command::new("javac")
-                .current_dir(self.venv.clone().unwrap())
-                .arg(self.entry.to_str().unwrap())
-                .stderr(Stdio::piped())
-                .spawn()
-                .unwrap();
-            match compiler.wait().await {
-                Ok(s) => {
-                    if s.code().unwrap() == 0 {
-                        Ok(())
-                    } else {
-                        let mut r = String::new();
-                        let _ = compiler.stderr.unwrap().read_to_string(&mut r).await;
-                        Err(RunError::CE(Some(s.code().unwrap()), r))
-                    }
-                }
-                Err(e) => Err(RunError::CE(None, e.to_string())),
-            }
-        }
-    }
-    async fn stdin(&mut self, input: String) -> Result<(), String> {
-        match &mut self.process {
-            Some(s) => s
-                .stdin
-                .as_mut()
-                .unwrap()
-                .write_all(input.as_bytes())
-                .await
-                .map_err(|e| format!("{e}"))
-                .map(|()| ()),
-            None => Err("Process has not started yet!".into()),
-        }
-    }
-    async fn stdout(&mut self) -> Option<&mut ChildStdout> {
-        match &mut self.process {
-            Some(s) => match &mut s.stdout {
-                Some(t) => return Some(t),
-                None => return None,
-            },
-            None => return None,
-        };
-    }
-    async fn exitcode(&mut self) -> Result<Option<ExitStatus>, std::io::Error> {
-        if self.running().await {
-            self.process.as_mut().unwrap().try_wait()
-        } else {
-            Ok(None)
-        }
-    }
-    async fn read_all(&mut self) -> Result<String, String> {
-        let stdout = match &mut self.process {
-            Some(s) => match &mut s.stdout {
-                Some(t) => t,
-                None => return Err("Stdout is not open!".into()),
-            },
-            None => return Err("Process is not running!".into()),
-        };
-        let mut buf: String = String::new();
-        let _ = stdout.read_to_string(&mut buf).await;
-        Ok(buf)
+        self.compile_sources().await?;
+        self.compile_harness().await
     }
     async fn new_from_venv(venv: PathBuf, entry: PathBuf) -> Result<Self, Error> {
-        let mut ret;
         let ext: String = entry.extension().unwrap().to_string_lossy().into();
         match ext.as_str() {
-            "java" => {
-                debug!("detected bare java file.");
-                ret = Self {
-                    start: None,
-                    command: Command::new("java"),
-                    process: None,
-                    venv: Some(venv.clone()),
-                    entry: entry.clone(),
-                    deps: vec![],
-                    exitcode: OnceLock::new(),
-                };
-                ret.command
-                    .arg("-cp")
-                    .arg(venv.to_str().unwrap())
-                    .arg(entry.file_stem().unwrap())
-                    .stdin(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .stdout(Stdio::piped());
-            }
-            "jar" => {
-                debug!("detected java executable archive.");
-                ret = Self {
-                    start: None,
-                    command: Command::new("java"),
-                    process: None,
-                    venv: Some(venv),
-                    entry: entry.clone(),
-                    deps: vec![],
-                    exitcode: OnceLock::new(),
-                };
-                ret.command
-                    .arg("--jar")
-                    .arg(&entry)
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped());
-            }
-            _ => panic!("give me a java file."),
+            "java" => debug!("detected bare java file."),
+            "jar" => debug!("detected java executable archive."),
+            _ => return Err(Error::new("give me a java file.")),
         }
-        Ok(ret)
+        Ok(Self {
+            venv: Some(venv),
+            entry,
+            deps: vec![],
+            classpath: vec![],
+            broken: HashMap::new(),
+        })
     }
-    async fn run(&mut self) -> Result<(), RunError> {
-        let mut contains = false;
-        for i in self.venv.as_ref().unwrap().read_dir().unwrap() {
-            if i.unwrap().path().extension().unwrap().to_str().unwrap() == "class" {
-                contains = true;
-                break;
+    /// Method-level cases launch their generated class instead of the entry point.
+    fn case_command(&self, limits: &Limits, case: &TestCase) -> Result<Command, RunError> {
+        let mut command = if case.call.is_some() {
+            let n = case.index;
+            if let Some(errors) = self.broken.get(&n) {
+                return Err(RunError::CE(None, errors.clone()));
             }
-        }
-        if !contains {
-            debug!("Hasn't been compiled and prepared yet! Compiling...");
-            self.prepare().await?;
-        }
-        self.process = Some(self.command.spawn().unwrap());
-        self.start = Some(Instant::now());
-        Ok(())
+            let mut command = self.java(limits);
+            command
+                .arg("-cp")
+                .arg(self.harness_classpath())
+                .arg(harness::class_name(n));
+            command
+        } else {
+            self.command(limits)?
+        };
+        command.args(&case.args).envs(&case.env);
+        Ok(command)
     }
-    async fn running(&mut self) -> bool {
-        match &mut self.process {
-            Some(s) => match s.try_wait().unwrap() {
-                Some(s) => {
-                    let _ = self.exitcode.set(s.code().unwrap());
-                    false
+    /// Builds the `java` invocation for the entry point.
+    ///
+    /// Jars run with `-jar` unless `entry` is set and names a class inside the jar, or dependency
+    /// jars have to be put on the classpath, since `-jar` ignores `-cp`. The memory limit becomes
+    /// the heap size, with an eighth of it (up to 256MB) as thread stack size for deep recursion.
+    fn command(&self, limits: &Limits) -> Result<Command, RunError> {
+        let mut command = self.java(limits);
+        if self.is_jar() {
+            let entry = self.runs_entry();
+            let main = if entry {
+                debug!("using entry {} as main class.", CONFIG.entry);
+                Some(CONFIG.entry.clone())
+            } else {
+                manifest_main_class(&self.entry)
+            };
+            match main {
+                Some(m) if entry || !self.classpath.is_empty() => {
+                    command.arg("-cp").arg(self.classpath()).arg(m);
                 }
-                None => true,
-            },
-            None => false,
+                Some(_) => {
+                    command.arg("-jar").arg(&self.entry);
+                }
+                None if CONFIG.entry_given => {
+                    return Err(RunError::RE(
+                        None,
+                        format!(
+                            "{} has no Main-Class and does not contain {}!",
+                            self.entry.to_str().unwrap(),
+                            CONFIG.entry
+                        ),
+                    ));
+                }
+                None => {
+                    return Err(RunError::RE(
+                        None,
+                        format!(
+                            "{} has no Main-Class, set entry to the class to run!",
+                            self.entry.to_str().unwrap()
+                        ),
+                    ));
+                }
+            }
+        } else {
+            command
+                .arg("-cp")
+                .arg(self.classpath())
+                .arg(self.main_class());
         }
+        Ok(command)
+    }
+    /// The JVM reserves far more address space than it uses, so only the cgroup may cap it on
+    /// top of the heap flags.
+    async fn run(&self, limits: &Limits, case: &TestCase) -> Result<Execution, RunError> {
+        Execution::spawn(
+            self.case_command(limits, case)?,
+            &limits.without_address_space(),
+        )
     }
     async fn get_lang(&self) -> Language {
         Language::Java
     }
-    #[cfg(unix)]
-    async fn signal(&mut self, s: Signal) -> Result<(), String> {
-        #[cfg(unix)]
-        let pid = nix::unistd::Pid::from_raw(if let Some(c) = &self.process {
-            c.id().unwrap() as i32
-        } else {
-            log::error!("tried to kill PID that does not exist!");
-            return Err("tried to kill PID that does not exist".into());
-        });
-        #[cfg(unix)]
-        match kill(pid, s) {
-            Err(e) => {
-                log::error!("failed to kill PID {pid}! error: {e}");
-                return Err(e.to_string());
-            }
-            Ok(()) => {}
-        }
-        Ok(())
-    }
-    async fn runtime(&self) -> Result<Duration, ()> {
-        self.start.as_ref().map_or(Err(()), |s| Ok(s.elapsed()))
-    }
 }
@@ -1,3 +1,15 @@
+pub mod c;
+pub mod execution;
+pub mod generic;
+pub mod harness;
+#[cfg(target_os = "linux")]
+pub mod isolation;
 pub mod java;
+pub mod python;
+pub mod registry;
 pub mod runner;
+pub mod rust;
+pub mod sandbox;
+#[cfg(target_os = "linux")]
+pub mod seccomp;
 pub mod unknown;
@@ -0,0 +1,88 @@
+use super::runner::{Error, RunError, Runner};
+use super::sandbox::Limits;
+use crate::config::CONFIG;
+use crate::executable::Language;
+use async_trait::async_trait;
+use log::debug;
+use std::{fs::create_dir_all, path::PathBuf, process::Stdio};
+use tokio::{fs::copy, io::AsyncReadExt as _, process::Command};
+
+pub struct PythonRunner {
+    venv: Option<PathBuf>,
+    entry: PathBuf,
+    deps: Vec<PathBuf>,
+}
+
+#[async_trait]
+impl Runner for PythonRunner {
+    async fn add_dep(&mut self, p: PathBuf) -> Result<(), String> {
+        self.deps.push(p.clone());
+        let target = self
+            .venv
+            .clone()
+            .unwrap()
+            .join(PathBuf::from(p.file_name().unwrap()));
+        copy(p, target).await.map_err(|e| format!("{e}"))?;
+        Ok(())
+    }
+    async fn add_deps(&mut self, p: Vec<PathBuf>) -> Result<(), String> {
+        self.deps.extend(p.clone());
+        let venvdir = self.venv.clone();
+        create_dir_all(venvdir.clone().unwrap()).map_err(|e| format!("{e}"))?;
+        for i in p {
+            let target = venvdir
+                .clone()
+                .unwrap()
+                .join(PathBuf::from(i.file_name().unwrap()));
+            copy(i, target).await.map_err(|e| format!("{e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Python is not compiled, but a byte-compile pass catches syntax errors up front so they
+    /// are reported as compile errors instead of failing every test case.
+    async fn prepare(&mut self) -> Result<(), RunError> {
+        let mut compiler = Command::new(&CONFIG.toolchain.python)
+            .current_dir(self.venv.clone().unwrap())
+            .arg("-m")
+            .arg("py_compile")
+            .arg(self.entry.to_str().unwrap())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::CE(None, e.to_string()))?;
+        let mut r = String::new();
+        let _ = compiler.stderr.take().unwrap().read_to_string(&mut r).await;
+        match compiler.wait().await {
+            Ok(s) => {
+                if s.code() == Some(0) {
+                    Ok(())
+                } else {
+                    Err(RunError::CE(s.code(), r))
+                }
+            }
+            Err(e) => Err(RunError::CE(None, e.to_string())),
+        }
+    }
+    async fn new_from_venv(venv: PathBuf, entry: PathBuf) -> Result<Self, Error> {
+        let ext: String = entry.extension().unwrap().to_string_lossy().into();
+        if ext != "py" {
+            return Err(Error::new("give me a python file."));
+        }
+        debug!("detected python script.");
+        Ok(Self {
+            venv: Some(venv),
+            entry,
+            deps: vec![],
+        })
+    }
+    fn command(&self, _limits: &Limits) -> Result<Command, RunError> {
+        let mut command = Command::new(&CONFIG.toolchain.python);
+        command
+            .current_dir(self.venv.clone().unwrap())
+            .arg(&self.entry);
+        Ok(command)
+    }
+    async fn get_lang(&self) -> Language {
+        Language::Python
+    }
+}
@@ -0,0 +1,135 @@
+//! Registry of the runners apcs-tester can build.
+//!
+//! Every language is a [`Registration`] that says which files belong to it and how to build its
+//! runner. The built-in runners and the ones defined in the config are registered on first use;
+//! more can be added with [`register`].
+
+use super::c::{CRunner, CppRunner};
+use super::generic::GenericRunner;
+use super::java::JavaRunner;
+use super::python::PythonRunner;
+use super::runner::{Error, Runner};
+use super::rust::RustRunner;
+use crate::config::CONFIG;
+use crate::executable::Language;
+use core::{future::Future, pin::Pin};
+use log::debug;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+use walkdir::WalkDir;
+
+pub type RunnerFuture = Pin<Box<dyn Future<Output = Result<Box<dyn Runner>, Error>> + Send>>;
+pub type Constructor = Box<dyn Fn(PathBuf, PathBuf) -> RunnerFuture + Send + Sync>;
+
+#[non_exhaustive]
+pub struct Registration {
+    pub lang: Language,
+    /// extensions of source files, used to find the entry point
+    pub extensions: Vec<String>,
+    /// claims a submission directory before extensions are looked at(e.g. a `Cargo.toml`)
+    pub detector: Option<fn(&Path) -> bool>,
+    /// builds the runner from the venv and the entry point
+    pub constructor: Constructor,
+}
+
+impl Registration {
+    #[must_use]
+    pub fn new(lang: Language, extensions: &[&str], constructor: Constructor) -> Self {
+        Self {
+            lang,
+            extensions: extensions.iter().map(|el| (*el).to_owned()).collect(),
+            detector: None,
+            constructor,
+        }
+    }
+    #[must_use]
+    pub fn with_detector(mut self, detector: fn(&Path) -> bool) -> Self {
+        self.detector = Some(detector);
+        self
+    }
+    /// Registration for a runner that is built through [`Runner::new_from_venv`].
+    #[must_use]
+    pub fn of<R: Runner + 'static>(lang: Language, extensions: &[&str]) -> Self {
+        Self::new(
+            lang,
+            extensions,
+            Box::new(|venv, entry| {
+                Box::pin(async move {
+                    R::new_from_venv(venv, entry)
+                        .await
+                        .map(|el| Box::new(el) as Box<dyn Runner>)
+                })
+            }),
+        )
+    }
+    #[must_use]
+    pub fn owns(&self, p: &Path) -> bool {
+        p.extension()
+            .is_some_and(|e| self.extensions.iter().any(|el| el.as_str() == e))
+    }
+}
+
+static REGISTRY: LazyLock<RwLock<Vec<Registration>>> = LazyLock::new(|| {
+    let mut ret = vec![];
+    // config-defined runners come first so they can take over extensions from the built-in ones.
+    for spec in &CONFIG.runners {
+        let owned = spec.clone();
+        let exts: Vec<&str> = spec.extensions.iter().map(String::as_str).collect();
+        ret.push(Registration::new(
+            Language::Custom(spec.name.clone()),
+            &exts,
+            Box::new(move |venv, entry| {
+                let spec = owned.clone();
+                Box::pin(async move {
+                    Ok(Box::new(GenericRunner::with_spec(spec, venv, entry)) as Box<dyn Runner>)
+                })
+            }),
+        ));
+    }
+    ret.push(
+        Registration::of::<RustRunner>(Language::Rust, &["rs"])
+            .with_detector(|p| p.join("Cargo.toml").is_file()),
+    );
+    ret.push(Registration::of::<JavaRunner>(
+        Language::Java,
+        &["java", "jar"],
+    ));
+    ret.push(Registration::of::<CppRunner>(
+        Language::Cpp,
+        &["cpp", "cc", "cxx"],
+    ));
+    ret.push(Registration::of::<CRunner>(Language::C, &["c"]));
+    ret.push(Registration::of::<PythonRunner>(Language::Python, &["py"]));
+    RwLock::new(ret)
+});
+
+/// Adds a runner to the registry. Later registrations lose to earlier ones on conflicts.
+pub fn register(r: Registration) {
+    debug!("registering runner for {:?}", r.lang);
+    REGISTRY.write().unwrap().push(r);
+}
+
+/// Runs `f` on the registration for `lang`.
+pub fn with_lang<T>(lang: &Language, f: impl FnOnce(&Registration) -> T) -> Option<T> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .find(|el| el.lang == *lang)
+        .map(f)
+}
+
+/// Guesses which registered language a submission directory is written in.
+#[must_use]
+pub fn detect(p: &Path) -> Option<Language> {
+    let registry = REGISTRY.read().unwrap();
+    if let Some(r) = registry.iter().find(|el| el.detector.is_some_and(|d| d(p))) {
+        return Some(r.lang.clone());
+    }
+    for e in WalkDir::new(p).into_iter().filter_map(Result::ok) {
+        if let Some(r) = registry.iter().find(|el| el.owns(e.path())) {
+            return Some(r.lang.clone());
+        }
+    }
+    None
+}
@@ -1,17 +1,16 @@
-use super::java::JavaRunner;
-use crate::{config::CONFIG, executable::Language, unpacker::find_in_dir};
+use super::execution::{Compilation, Execution};
+use super::registry::{self, Registration};
+use super::sandbox::Limits;
+use crate::{config::CONFIG, executable::Language, test::TestCase};
 use async_trait::async_trait;
 use log::{debug, error, warn};
-#[cfg(unix)]
-use nix::sys::signal::Signal;
 use std::{
     fmt::{Display, Formatter},
-    path::PathBuf,
-    process::ExitStatus,
-    time,
+    path::{Path, PathBuf},
+    time::Instant,
 };
-use tokio::fs::copy;
-use tokio::process::ChildStdout;
+use tokio::process::Command;
+use walkdir::WalkDir;
 
 #[derive(Debug)]
 #[non_exhaustive]
@@ -32,54 +31,78 @@ impl Display for Error {
     }
 }
 
-pub async fn from_dir(p: PathBuf, lang: Option<Language>) -> Option<Box<dyn Runner>> {
-    //probe
-    if lang.is_some() && lang.unwrap() != Language::Java {
-        error!("Language other than java not yet implemented!");
-        return None;
+/// Finds the entry point of a submission among the source files of `lang`.
+///
+/// Prefers the configured entry, then anything called "main". If neither exists and the
+/// submission only has one source file, that file is used.
+fn find_entry(p: &PathBuf, reg: &Registration) -> Option<PathBuf> {
+    let sources: Vec<PathBuf> = WalkDir::new(p)
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(walkdir::DirEntry::into_path)
+        .filter(|el| el.is_file() && reg.owns(el))
+        .collect();
+    let matching = |target: &str| {
+        sources
+            .iter()
+            .find(|el| {
+                el.file_stem()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_lowercase()
+                    .contains(&target.to_lowercase())
+            })
+            .cloned()
+    };
+    if let Some(s) = matching(&CONFIG.entry) {
+        return Some(s);
     }
-    for i in &CONFIG.dependencies {
-        if copy(i, p.clone().join(i.file_name().unwrap()))
-            .await
-            .is_err()
-        {
-            error!("Failed to copy dependency: {i:?}");
-        }
+    warn!("Failed to find entry point! Falling back to \"Main\".");
+    if let Some(s) = matching("main") {
+        return Some(s);
     }
-    let entry = match find_in_dir(&p, &CONFIG.entry)
-        .or_else(|| find_in_dir(&p, &CONFIG.entry.clone().to_lowercase()))
-    {
-        Some(s) => s,
-        None => {
-            warn!("Failed to find entry point! Falling back to \"Main\".");
-            match find_in_dir(&p, "main").or_else(|| find_in_dir(&p, "Main")) {
-                Some(s) => s,
-                None => {
-                    error!("Failed to find main!");
-                    if p.read_dir().unwrap().count() > 1 {
-                        error!("There are too many files! Failed to determine which one to use!");
-                        return None;
-                    }
-                    warn!("Will run any file inside target directory.");
-                    p.read_dir()
-                        .unwrap()
-                        .next()
-                        .unwrap()
-                        .ok()
-                        .map(|el| return el.path())
-                        .unwrap()
-                }
+    error!("Failed to find main!");
+    if sources.len() > 1 {
+        error!("There are too many files! Failed to determine which one to use!");
+        return None;
+    }
+    warn!("Will run any file inside target directory.");
+    sources.into_iter().next()
+}
+
+/// Builds a runner for the submission in `p` through the [`registry`].
+///
+/// If `lang` is `None` or `Guess`, the language is detected from the files in the submission, so
+/// every directory can be in a different language.
+pub async fn from_dir(p: PathBuf, lang: Option<Language>) -> Option<Box<dyn Runner>> {
+    //probe
+    let lang = match lang {
+        None | Some(Language::Guess) => match registry::detect(&p) {
+            Some(l) => l,
+            None => {
+                error!("Could not determine the language of {p:?}!");
+                return None;
             }
-        }
+        },
+        Some(l) => l,
+    };
+    debug!("Language of {p:?}: {lang:?}");
+    let Some(Some((entry, runner))) = registry::with_lang(&lang, |reg| {
+        find_entry(&p, reg).map(|entry| (entry.clone(), (reg.constructor)(p.clone(), entry)))
+    }) else {
+        error!("No runner available for {lang:?} in {p:?}!");
+        return None;
     };
     debug!("Finished probing. Entry point: {entry:?}");
-    match entry.extension().unwrap().to_str().unwrap() {
-        "java" => Some(Box::new(JavaRunner::new_from_venv(p, entry).await.unwrap())),
-        ext => {
-            error!("Unknown extension: {ext}");
-            None
-        }
+    let mut runner = runner
+        .await
+        .inspect_err(|e| error!("Failed to create runner: {e}"))
+        .ok()?;
+    if let Err(e) = runner.add_deps(CONFIG.dependencies.clone()).await {
+        error!("Failed to add dependencies: {e}");
     }
+    Some(runner)
 }
 
 impl core::error::Error for Error {}
@@ -93,20 +116,45 @@ pub enum RunError {
 
 #[async_trait]
 pub trait Runner: Send + Sync {
+    /// Compiles the submission. Called once, before any test case runs.
     async fn prepare(&mut self) -> Result<(), RunError>;
     async fn new_from_venv(p: PathBuf, entry: PathBuf) -> Result<Self, Error>
     where
         Self: Sized;
-    async fn running(&mut self) -> bool;
-    async fn run(&mut self) -> Result<(), RunError>;
+    /// Command that launches the prepared submission. Runtimes that enforce `limits` themselves
+    /// turn them into flags here.
+    fn command(&self, limits: &Limits) -> Result<Command, RunError>;
     async fn get_lang(&self) -> crate::executable::Language;
-    async fn stdin(&mut self, s: String) -> Result<(), String>;
-    async fn stdout(&mut self) -> Option<&mut ChildStdout>;
-    async fn read_all(&mut self) -> Result<String, String>;
-    async fn runtime(&self) -> Result<time::Duration, ()>;
-    #[cfg(unix)]
-    async fn signal(&mut self, s: Signal) -> Result<(), String>;
-    async fn exitcode(&mut self) -> Result<Option<ExitStatus>, std::io::Error>;
     async fn add_dep(&mut self, p: PathBuf) -> Result<(), String>;
     async fn add_deps(&mut self, p: Vec<PathBuf>) -> Result<(), String>;
+    /// Runs [`Runner::prepare`] and measures how long it took.
+    async fn compile(&mut self) -> Result<Compilation, RunError> {
+        let start = Instant::now();
+        self.prepare().await?;
+        Ok(Compilation {
+            time: start.elapsed(),
+        })
+    }
+    /// [`Runner::command`] with the arguments and environment of `case`. Only Java runners can
+    /// call methods, so other runners fail method-level cases.
+    fn case_command(&self, limits: &Limits, case: &TestCase) -> Result<Command, RunError> {
+        if case.call.is_some() {
+            return Err(RunError::CE(
+                None,
+                "method calls need a Java submission".into(),
+            ));
+        }
+        let mut command = self.command(limits)?;
+        command.args(&case.args).envs(&case.env);
+        Ok(command)
+    }
+    /// Directory the submission runs in, where the files of a case are placed.
+    fn workdir(&self, limits: &Limits) -> Option<PathBuf> {
+        let command = self.command(limits).ok()?;
+        command.as_std().get_current_dir().map(Path::to_path_buf)
+    }
+    /// Spawns a fresh process from the prepared submission for `case`.
+    async fn run(&self, limits: &Limits, case: &TestCase) -> Result<Execution, RunError> {
+        Execution::spawn(self.case_command(limits, case)?, limits)
+    }
 }
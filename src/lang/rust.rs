@@ -0,0 +1,201 @@
+use super::runner::{Error, RunError, Runner};
+use super::sandbox::Limits;
+use crate::config::CONFIG;
+use crate::executable::Language;
+use async_trait::async_trait;
+use log::debug;
+use std::{fs::create_dir_all, path::PathBuf, process::Stdio};
+use tokio::{fs::copy, io::AsyncReadExt as _, process::Command};
+
+/// Runner for Rust submissions.
+///
+/// A bare `main.rs` is compiled with rustc. If a `Cargo.toml` sits between the entry and the venv,
+/// the whole project is built with cargo in offline mode, using a `vendor` directory as the only
+/// crate source when the submission ships one.
+pub struct RustRunner {
+    venv: Option<PathBuf>,
+    entry: PathBuf,
+    manifest: Option<PathBuf>,
+    binary: Option<PathBuf>,
+    deps: Vec<PathBuf>,
+}
+
+impl RustRunner {
+    fn find_manifest(venv: &PathBuf, entry: &PathBuf) -> Option<PathBuf> {
+        let mut dir = entry.parent();
+        while let Some(d) = dir {
+            if d.join("Cargo.toml").is_file() {
+                return Some(d.join("Cargo.toml"));
+            }
+            if d == venv.as_path() {
+                break;
+            }
+            dir = d.parent();
+        }
+        None
+    }
+    async fn rustc(&mut self) -> Result<(), RunError> {
+        let binary = self
+            .venv
+            .clone()
+            .unwrap()
+            .join(self.entry.file_stem().unwrap())
+            .with_extension("out");
+        let mut compiler = Command::new(&CONFIG.toolchain.rustc)
+            .current_dir(self.venv.clone().unwrap())
+            .arg("--edition=2021")
+            .arg("-O")
+            .arg(&self.entry)
+            .arg("-o")
+            .arg(&binary)
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::CE(None, e.to_string()))?;
+        let mut r = String::new();
+        let _ = compiler.stderr.take().unwrap().read_to_string(&mut r).await;
+        match compiler.wait().await {
+            Ok(s) => {
+                if s.code() == Some(0) {
+                    self.binary = Some(binary);
+                    Ok(())
+                } else {
+                    Err(RunError::CE(s.code(), r))
+                }
+            }
+            Err(e) => Err(RunError::CE(None, e.to_string())),
+        }
+    }
+    async fn cargo(&mut self, manifest: PathBuf) -> Result<(), RunError> {
+        let project = manifest.parent().unwrap().to_path_buf();
+        let mut command = Command::new(&CONFIG.toolchain.cargo);
+        command
+            .current_dir(&project)
+            .env("CARGO_TARGET_DIR", project.join("target"))
+            .env("CARGO_NET_OFFLINE", "true")
+            .arg("build")
+            .arg("--release")
+            .arg("--offline")
+            .arg("--message-format=json-render-diagnostics")
+            .arg("--manifest-path")
+            .arg(&manifest);
+        if project.join("vendor").is_dir() {
+            debug!("using vendored crates in {:?}", project.join("vendor"));
+            command
+                .arg("--config")
+                .arg("source.crates-io.replace-with=\"vendored-sources\"")
+                .arg("--config")
+                .arg(format!(
+                    "source.vendored-sources.directory={:?}",
+                    project.join("vendor")
+                ));
+        }
+        let mut compiler = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::CE(None, e.to_string()))?;
+        let mut out = String::new();
+        let mut err = String::new();
+        let mut stdout = compiler.stdout.take().unwrap();
+        let mut stderr = compiler.stderr.take().unwrap();
+        let _ = tokio::join!(
+            stdout.read_to_string(&mut out),
+            stderr.read_to_string(&mut err)
+        );
+        let status = compiler
+            .wait()
+            .await
+            .map_err(|e| RunError::CE(None, e.to_string()))?;
+        if status.code() != Some(0) {
+            return Err(RunError::CE(status.code(), err));
+        }
+        // cargo reports every produced artifact; the last executable is the binary of the root package.
+        self.binary = out
+            .lines()
+            .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+            .filter(|v| v["reason"] == "compiler-artifact")
+            .filter_map(|v| v["executable"].as_str().map(PathBuf::from))
+            .last();
+        if self.binary.is_none() {
+            return Err(RunError::CE(
+                None,
+                "cargo did not produce an executable. Is this a binary crate?".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Runner for RustRunner {
+    async fn add_dep(&mut self, p: PathBuf) -> Result<(), String> {
+        self.deps.push(p.clone());
+        let target = self
+            .venv
+            .clone()
+            .unwrap()
+            .join(PathBuf::from(p.file_name().unwrap()));
+        copy(p, target).await.map_err(|e| format!("{e}"))?;
+        Ok(())
+    }
+    async fn add_deps(&mut self, p: Vec<PathBuf>) -> Result<(), String> {
+        self.deps.extend(p.clone());
+        let venvdir = self.venv.clone();
+        create_dir_all(venvdir.clone().unwrap()).map_err(|e| format!("{e}"))?;
+        for i in p {
+            let target = venvdir
+                .clone()
+                .unwrap()
+                .join(PathBuf::from(i.file_name().unwrap()));
+            copy(i, target).await.map_err(|e| format!("{e}"))?;
+        }
+        Ok(())
+    }
+
+    async fn prepare(&mut self) -> Result<(), RunError> {
+        match self.manifest.clone() {
+            Some(m) => {
+                debug!("building cargo project {m:?}");
+                self.cargo(m).await
+            }
+            None => {
+                debug!("compiling {:?} with rustc", self.entry);
+                self.rustc().await
+            }
+        }
+    }
+    async fn new_from_venv(venv: PathBuf, entry: PathBuf) -> Result<Self, Error> {
+        let manifest = if entry.file_name().is_some_and(|el| el == "Cargo.toml") {
+            Some(entry.clone())
+        } else if entry.extension().is_some_and(|el| el == "rs") {
+            Self::find_manifest(&venv, &entry)
+        } else {
+            return Err(Error::new("give me a rust file or a Cargo.toml."));
+        };
+        match &manifest {
+            Some(m) => debug!("detected cargo project at {m:?}"),
+            None => debug!("detected bare rust file."),
+        }
+        Ok(Self {
+            venv: Some(venv),
+            entry,
+            manifest,
+            binary: None,
+            deps: vec![],
+        })
+    }
+    fn command(&self, _limits: &Limits) -> Result<Command, RunError> {
+        let Some(binary) = &self.binary else {
+            return Err(RunError::RE(
+                None,
+                "submission has not been compiled!".into(),
+            ));
+        };
+        let mut command = Command::new(binary);
+        command.current_dir(self.venv.clone().unwrap());
+        Ok(command)
+    }
+    async fn get_lang(&self) -> Language {
+        Language::Rust
+    }
+}
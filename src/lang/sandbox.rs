@@ -0,0 +1,298 @@
+//! Resource limits for submissions.
+//!
+//! Every run gets its own session and process group, so it can be killed as a whole, and rlimits
+//! for CPU time, written file size and open files. The CPU rlimit only has a precision
+//! of seconds, so it is a backstop for the CPU time measured once the run is over.
+//!
+//! On Linux, syscalls that the `allow` list forbids are filtered with seccomp, see
+//! [`super::seccomp`].
+//!
+//! Memory is capped with a cgroup v2 when the tester has a delegated hierarchy it can write to,
+//! and with `RLIMIT_AS` otherwise. Processes are only capped through the cgroup's `pids.max`, since
+//! `RLIMIT_NPROC` counts every process of the user, not just the ones of the run. Runtimes that manage their own heap (the JVM) get their limit
+//! through flags instead, since capping their address space keeps them from starting at all.
+
+use crate::checker::static_check::{Allow, allowed};
+use crate::config::CONFIG;
+use crate::test::TestCase;
+use core::time::Duration;
+#[cfg(unix)]
+use log::debug;
+#[cfg(unix)]
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+#[cfg(unix)]
+use tokio::process::Command;
+
+/// Messages runtimes print when an allocation fails.
+const OOM_MARKERS: [&str; 4] = [
+    "java.lang.OutOfMemoryError",
+    "MemoryError",
+    "std::bad_alloc",
+    "memory allocation of",
+];
+
+/// Multiple of the wall-clock limit that the CPU time limit defaults to. Runtimes like the JVM
+/// spend CPU time on GC and JIT threads in parallel to the program, so a CPU limit equal to the
+/// wall-clock one would stop them first.
+const CPU_TIMEOUT_FACTOR: u64 = 2;
+
+/// Resource limits applied to a single execution.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Limits {
+    /// memory limit in MB
+    pub memory: Option<u64>,
+    /// whether `RLIMIT_AS` may be used when no cgroup is available
+    pub address_space: bool,
+    /// wall-clock time
+    pub wall: Option<Duration>,
+    /// CPU time
+    pub cpu: Option<Duration>,
+    /// maximum number of processes and threads, enforced through the cgroup only
+    pub processes: Option<u64>,
+    /// maximum number of open file descriptors
+    pub open_files: Option<u64>,
+    /// maximum size of a written file, in MB
+    pub file_size: Option<u64>,
+    /// maximum size of stdout and of stderr, in KB
+    pub output: Option<u64>,
+    /// whether to run in fresh namespaces, see [`super::isolation`]
+    pub isolate: bool,
+    /// syscalls to deny
+    pub denied: Syscalls,
+}
+
+/// Groups of syscalls that can be denied to a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Syscalls {
+    /// sockets other than unix domain sockets
+    pub network: bool,
+    /// `execve` and `execveat`, once the program has started
+    pub exec: bool,
+}
+
+impl Syscalls {
+    /// Everything the `allow` list does not allow, through `Network` and `ProcessExec`. Nothing
+    /// is denied if `All` is allowed.
+    pub fn from_config() -> Self {
+        let allowed = allowed();
+        let denied = |el: Allow| !allowed.contains(&Allow::All) && !allowed.contains(&el);
+        Self {
+            network: denied(Allow::Network),
+            exec: denied(Allow::ProcessExec),
+        }
+    }
+    pub const fn any(&self) -> bool {
+        self.network || self.exec
+    }
+}
+
+impl Limits {
+    /// Limits from the config. A limit of 0 disables it.
+    pub fn from_config() -> Self {
+        let nonzero = |el: u64| (el != 0).then_some(el);
+        Self {
+            memory: nonzero(CONFIG.memory),
+            address_space: true,
+            wall: nonzero(CONFIG.timeout).map(Duration::from_millis),
+            cpu: nonzero(
+                CONFIG
+                    .cpu_timeout
+                    .unwrap_or(CONFIG.timeout.saturating_mul(CPU_TIMEOUT_FACTOR)),
+            )
+            .map(Duration::from_millis),
+            processes: nonzero(CONFIG.limits.processes),
+            open_files: nonzero(CONFIG.limits.open_files),
+            file_size: nonzero(CONFIG.limits.file_size),
+            output: nonzero(CONFIG.limits.output),
+            isolate: CONFIG.isolate && cfg!(target_os = "linux"),
+            denied: Syscalls::from_config(),
+        }
+    }
+    /// Limits from the config, with the ones `case` overrides. Without a `cpu_timeout`, the CPU
+    /// time limit follows the wall-clock limit of the case.
+    pub fn for_case(case: &TestCase) -> Self {
+        let nonzero = |el: u64| (el != 0).then_some(el);
+        let global = Self::from_config();
+        let cpu = case.cpu_timeout.or(CONFIG.cpu_timeout).unwrap_or(
+            case.timeout
+                .unwrap_or(CONFIG.timeout)
+                .saturating_mul(CPU_TIMEOUT_FACTOR),
+        );
+        Self {
+            memory: case.memory.map_or(global.memory, nonzero),
+            wall: case
+                .timeout
+                .map_or(global.wall, |el| nonzero(el).map(Duration::from_millis)),
+            cpu: nonzero(cpu).map(Duration::from_millis),
+            ..global
+        }
+    }
+    /// Same limits, but without touching the address space of the process.
+    #[must_use]
+    pub fn without_address_space(&self) -> Self {
+        Self {
+            address_space: false,
+            ..self.clone()
+        }
+    }
+    fn memory_bytes(&self) -> Option<u64> {
+        self.memory.map(|el| el.saturating_mul(1024 * 1024))
+    }
+    pub fn output_bytes(&self) -> Option<usize> {
+        self.output
+            .map(|el| usize::try_from(el.saturating_mul(1024)).unwrap_or(usize::MAX))
+    }
+}
+
+/// Whether `stderr` shows that the program ran out of memory.
+pub fn reports_oom(stderr: &str) -> bool {
+    OOM_MARKERS.iter().any(|el| stderr.contains(el))
+}
+
+/// A cgroup created for a single execution, removed again when dropped.
+#[cfg(unix)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Cgroup {
+    /// Location of the cgroup v2 hierarchy the tester itself runs in.
+    fn current() -> Option<PathBuf> {
+        let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+        let mount = mounts
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>())
+            .find(|el| el.len() > 2 && el[2] == "cgroup2")
+            .map(|el| PathBuf::from(el[1]))?;
+        let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+        let own = cgroups.lines().find_map(|l| l.strip_prefix("0::"))?;
+        Some(mount.join(own.trim_start_matches('/')))
+    }
+    /// Creates a cgroup capped at `bytes` and at `pids` processes. Returns `None` if there is
+    /// nothing to cap, or the memory controller is not available to us when `bytes` is set.
+    fn create(bytes: Option<u64>, pids: Option<u64>) -> Option<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = Self::current()?.join(format!(
+            "apcs-tester-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if bytes.is_none() && pids.is_none() {
+            return None;
+        }
+        fs::create_dir(&path).ok()?;
+        let ret = Self { path };
+        if let Some(bytes) = bytes {
+            if fs::write(ret.path.join("memory.max"), bytes.to_string()).is_err() {
+                debug!("memory controller is not delegated to us, falling back to RLIMIT_AS.");
+                return None;
+            }
+            let _ = fs::write(ret.path.join("memory.swap.max"), "0");
+        }
+        if let Some(pids) = pids
+            && fs::write(ret.path.join("pids.max"), pids.to_string()).is_err()
+        {
+            debug!("pids controller is not delegated to us, processes are not limited.");
+            if bytes.is_none() {
+                return None;
+            }
+        }
+        Some(ret)
+    }
+    fn procs(&self) -> Option<File> {
+        OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))
+            .ok()
+    }
+    /// Highest memory usage of the cgroup in bytes.
+    pub fn peak(&self) -> Option<u64> {
+        fs::read_to_string(self.path.join("memory.peak"))
+            .ok()
+            .and_then(|el| el.trim().parse().ok())
+    }
+    /// CPU time all processes of the cgroup used so far.
+    pub fn cpu_time(&self) -> Option<Duration> {
+        fs::read_to_string(self.path.join("cpu.stat"))
+            .ok()?
+            .lines()
+            .find_map(|l| l.strip_prefix("usage_usec "))
+            .and_then(|el| el.trim().parse().ok())
+            .map(Duration::from_micros)
+    }
+    /// Whether the kernel killed a process of this cgroup for exceeding `memory.max`.
+    pub fn oom_killed(&self) -> bool {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events
+                    .lines()
+                    .find_map(|l| l.strip_prefix("oom_kill "))
+                    .and_then(|el| el.trim().parse::<u64>().ok())
+            })
+            .is_some_and(|el| el > 0)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            debug!("failed to remove cgroup {:?}: {e}", self.path);
+        }
+    }
+}
+
+/// Makes `command` start a new session and enforce `limits` once spawned. The returned cgroup
+/// has to outlive the process.
+#[cfg(unix)]
+pub fn apply(command: &mut Command, limits: &Limits) -> Option<Cgroup> {
+    use nix::sys::resource::{Resource, getrlimit, setrlimit};
+    let bytes = limits.memory_bytes();
+    let cgroup = Cgroup::create(bytes, limits.processes);
+    let procs = cgroup.as_ref().and_then(Cgroup::procs);
+    let address_space = bytes.filter(|_| limits.address_space && procs.is_none());
+    let file_size = limits.file_size.map(|el| el.saturating_mul(1024 * 1024));
+    // SIGXCPU a second after the limit, SIGKILL another second later.
+    let cpu = limits.cpu.map(|el| {
+        let secs = el.as_secs() + u64::from(el.subsec_nanos() > 0) + 1;
+        (secs, secs + 1)
+    });
+    // limits can only be lowered without privileges, so they are capped at the current ones.
+    let rlimits = [
+        (Resource::RLIMIT_AS, address_space.map(|el| (el, el))),
+        (Resource::RLIMIT_CPU, cpu),
+        (
+            Resource::RLIMIT_NOFILE,
+            limits.open_files.map(|el| (el, el)),
+        ),
+        (Resource::RLIMIT_FSIZE, file_size.map(|el| (el, el))),
+    ]
+    .map(|(resource, limit)| {
+        let (_, max) = getrlimit(resource).ok()?;
+        limit.map(|(soft, hard)| (resource, soft.min(max), hard.min(max)))
+    });
+    // SAFETY: the hook only issues setsid(2), write(2) and setrlimit(2), all async-signal-safe,
+    // and does not allocate.
+    unsafe {
+        command.pre_exec(move || {
+            nix::unistd::setsid()?;
+            if let Some(procs) = &procs {
+                (&*procs).write_all(b"0")?;
+            }
+            for (resource, soft, hard) in rlimits.iter().flatten() {
+                setrlimit(*resource, *soft, *hard)?;
+            }
+            Ok(())
+        });
+    }
+    cgroup
+}
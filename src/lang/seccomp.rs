@@ -0,0 +1,456 @@
+//! Syscall filtering for submissions.
+//!
+//! A seccomp-bpf filter built from the denied [`Syscalls`] is installed right before the program
+//! is executed. Denied syscalls are not answered by the kernel but handed to a supervisor thread
+//! through a user notification listener, which records the first violation, kills the process
+//! group and fails the call. That way the report can name the syscall instead of a bare signal.
+//!
+//! Since the filter is installed before the program itself is executed, `execve` is let through
+//! until the binary the command resolves to is running, so wrappers like the shims of pyenv can
+//! still start it. The binary is looked up in `PATH` with symlinks resolved and compared against
+//! `/proc/pid/exe`. A program that is a script only shows its interpreter once it runs, so its
+//! runs are not denied `execve` at all. Neither is anything denied on kernels without user
+//! notifications that can let a syscall continue, i.e. before 5.5.
+
+use super::sandbox::Syscalls;
+use log::{debug, warn};
+use std::{
+    ffi::CStr,
+    fs::{self, File},
+    io::{self, Read as _},
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd},
+        unix::fs::{MetadataExt as _, PermissionsExt as _},
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        Arc, LazyLock, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+};
+use tokio::process::Command;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+/// Syscall numbers with this bit set belong to the x32 ABI, which would bypass the filter.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+/// Lower half of the first argument, on little-endian targets.
+const OFFSET_ARG0: u32 = 16;
+
+/// How long the supervisor waits for a notification before checking whether to stop.
+const POLL_TIMEOUT: libc::c_int = 100;
+
+const fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn load(offset: u32) -> libc::sock_filter {
+    stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset)
+}
+
+fn ret(action: u32) -> libc::sock_filter {
+    stmt(libc::BPF_RET | libc::BPF_K, action)
+}
+
+/// Name of a syscall the filter can report.
+fn name(nr: i64) -> &'static str {
+    match nr {
+        libc::SYS_execve => return "execve",
+        libc::SYS_execveat => return "execveat",
+        libc::SYS_socket => return "socket",
+        _ => return "unknown",
+    }
+}
+
+/// Builds the filter. Syscalls of other architectures kill the process, denied ones go to the
+/// supervisor and everything else is allowed.
+fn program(denied: Syscalls) -> Vec<libc::sock_filter> {
+    let eq = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+    let mut filter = vec![
+        load(OFFSET_ARCH),
+        jump(eq, AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(OFFSET_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        jump(
+            libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K,
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        ),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+    if denied.exec {
+        for nr in [libc::SYS_execve, libc::SYS_execveat] {
+            filter.extend([jump(eq, nr as u32, 0, 1), ret(libc::SECCOMP_RET_USER_NOTIF)]);
+        }
+    }
+    // unix domain sockets stay available, runtimes use them for local IPC.
+    if denied.network {
+        filter.extend([
+            jump(eq, libc::SYS_socket as u32, 0, 3),
+            load(OFFSET_ARG0),
+            jump(eq, libc::AF_UNIX as u32, 1, 0),
+            ret(libc::SECCOMP_RET_USER_NOTIF),
+        ]);
+    }
+    filter.push(ret(libc::SECCOMP_RET_ALLOW));
+    filter
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+/// Installs `filter` and passes its listener over `socket`. Runs in the child after fork.
+fn install(filter: &[libc::sock_filter], socket: RawFd) -> io::Result<()> {
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr().cast_mut(),
+    };
+    // SAFETY: only async-signal-safe calls on memory that was allocated before the fork.
+    unsafe {
+        check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+        let listener = libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            ptr::from_ref(&prog),
+        );
+        if listener < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let listener = listener as RawFd;
+        let mut byte = 0u8;
+        let mut iov = libc::iovec {
+            iov_base: (&raw mut byte).cast(),
+            iov_len: 1,
+        };
+        let mut control = [0u64; 4];
+        let mut msg: libc::msghdr = MaybeUninit::zeroed().assume_init();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(size_of::<RawFd>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), listener);
+        let sent = libc::sendmsg(socket, &raw const msg, 0);
+        libc::close(listener);
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Receives the listener sent by [`install`]. `None` if the child never sent one.
+fn receive(socket: &OwnedFd) -> Option<OwnedFd> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&raw mut byte).cast(),
+        iov_len: 1,
+    };
+    let mut control = [0u64; 4];
+    // SAFETY: the buffers outlive the call, and the descriptor is only taken if the kernel
+    // attached one.
+    unsafe {
+        let mut msg: libc::msghdr = MaybeUninit::zeroed().assume_init();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = size_of_val(&control) as _;
+        loop {
+            if libc::recvmsg(socket.as_raw_fd(), &raw mut msg, libc::MSG_CMSG_CLOEXEC) > 0 {
+                break;
+            }
+            if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                return None;
+            }
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        if cmsg.is_null() || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+            return None;
+        }
+        let fd = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+        Some(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// Whether the kernel can hand syscalls to a supervisor and let them continue afterwards. Warns
+/// once if it cannot.
+static SUPPORTED: LazyLock<bool> = LazyLock::new(|| {
+    let action = libc::SECCOMP_RET_USER_NOTIF;
+    // SAFETY: `action` outlives the call.
+    let notif = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_GET_ACTION_AVAIL,
+            0,
+            ptr::from_ref(&action),
+        )
+    } == 0;
+    // `SECCOMP_USER_NOTIF_FLAG_CONTINUE` cannot be probed, it came with 5.5.
+    let cont = kernel().is_some_and(|el| el >= (5, 5));
+    if !(notif && cont) {
+        warn!("seccomp user notifications are not supported, syscalls are not filtered.");
+    }
+    notif && cont
+});
+
+/// Major and minor version of the running kernel.
+fn kernel() -> Option<(u32, u32)> {
+    // SAFETY: `utsname` is plain data, and the kernel fills in its strings.
+    let release = unsafe {
+        let mut uts: libc::utsname = MaybeUninit::zeroed().assume_init();
+        if libc::uname(&raw mut uts) < 0 {
+            return None;
+        }
+        CStr::from_ptr(uts.release.as_ptr())
+            .to_string_lossy()
+            .into_owned()
+    };
+    let mut it = release
+        .split(|el: char| !el.is_ascii_digit())
+        .map(str::parse::<u32>);
+    Some((it.next()?.ok()?, it.next()?.ok()?))
+}
+
+/// The binary `command` runs, looked up in its `PATH` like `execvp` does, with symlinks resolved.
+/// `None` if there is none, or if it is a script. If the working directory of the command is
+/// replaced by `workdir`, like for isolated runs, binaries inside it are looked up there.
+fn resolve(command: &Command, workdir: Option<&Path>) -> Option<PathBuf> {
+    let command = command.as_std();
+    let program = Path::new(command.get_program());
+    let dir = command.get_current_dir();
+    let found = if program.components().count() > 1 {
+        dir.map_or_else(|| program.to_path_buf(), |el| el.join(program))
+    } else {
+        let path = command
+            .get_envs()
+            .find(|el| el.0 == "PATH")
+            .map_or_else(|| std::env::var_os("PATH"), |el| el.1.map(Into::into))?;
+        std::env::split_paths(&path)
+            .map(|el| el.join(program))
+            .find(|el| {
+                fs::metadata(el)
+                    .is_ok_and(|el| el.is_file() && el.permissions().mode() & 0o111 != 0)
+            })?
+    };
+    let mut found = fs::canonicalize(found).ok()?;
+    if let (Some(dir), Some(workdir)) = (dir.and_then(|el| fs::canonicalize(el).ok()), workdir)
+        && let Ok(rest) = found.strip_prefix(&dir)
+    {
+        found = workdir.join(rest);
+    }
+    let mut magic = [0u8; 2];
+    File::open(&found).ok()?.read_exact(&mut magic).ok()?;
+    if &magic == b"#!" {
+        return None;
+    }
+    Some(found)
+}
+
+/// Whether `pid` runs `binary`, so the program has started and may not execute anything else.
+fn running(pid: u32, binary: &fs::Metadata) -> bool {
+    let Ok(exe) = fs::metadata(format!("/proc/{pid}/exe")) else {
+        // gone already, or not ours to inspect.
+        return true;
+    };
+    exe.dev() == binary.dev() && exe.ino() == binary.ino()
+}
+
+/// Kills the process group `pgid`. If its leader is still the tester, i.e. the intermediate
+/// process of an isolated run, only its children are killed, so it reaps them and its exit still
+/// reports the CPU time they used.
+fn kill_group(pgid: libc::pid_t) {
+    let tester = (
+        fs::metadata(format!("/proc/{pgid}/exe")),
+        std::env::current_exe().and_then(fs::metadata),
+    );
+    if let (Ok(exe), Ok(own)) = tester
+        && exe.dev() == own.dev()
+        && exe.ino() == own.ino()
+    {
+        let children =
+            fs::read_to_string(format!("/proc/{pgid}/task/{pgid}/children")).unwrap_or_default();
+        for child in children.split_whitespace().filter_map(|el| el.parse().ok()) {
+            // SAFETY: a plain syscall without pointers.
+            unsafe { libc::kill(child, libc::SIGKILL) };
+        }
+        return;
+    }
+    // SAFETY: a plain syscall without pointers.
+    unsafe { libc::killpg(pgid, libc::SIGKILL) };
+}
+
+/// Answers notifications until the filtered processes are gone or `stop` is set.
+fn supervise(
+    socket: &OwnedFd,
+    binary: Option<&fs::Metadata>,
+    stop: &AtomicBool,
+    violation: &OnceLock<String>,
+) {
+    let Some(listener) = receive(socket) else {
+        debug!("no seccomp listener received, the filter is not active.");
+        return;
+    };
+    let fd = listener.as_raw_fd();
+    while !stop.load(Ordering::Relaxed) {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `pollfd` is valid for the call.
+        let ready = unsafe { libc::poll(&raw mut pollfd, 1, POLL_TIMEOUT) };
+        if ready <= 0 {
+            continue;
+        }
+        if pollfd.revents & libc::POLLIN == 0 {
+            break;
+        }
+        // SAFETY: the kernel requires a zeroed buffer, and `seccomp_notif` is plain data.
+        let mut req: libc::seccomp_notif = unsafe { MaybeUninit::zeroed().assume_init() };
+        // SAFETY: `req` matches the size encoded in the ioctl request.
+        if unsafe { libc::ioctl(fd, libc::SECCOMP_IOCTL_NOTIF_RECV, &raw mut req) } < 0 {
+            // the process died before its notification was received.
+            continue;
+        }
+        let nr = i64::from(req.data.nr);
+        let mut resp = libc::seccomp_notif_resp {
+            id: req.id,
+            val: 0,
+            error: 0,
+            flags: 0,
+        };
+        if (nr == libc::SYS_execve || nr == libc::SYS_execveat)
+            && binary.is_some_and(|el| !running(req.pid, el))
+        {
+            resp.flags = libc::SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32;
+        } else {
+            violation.get_or_init(|| name(nr).to_owned());
+            // SAFETY: a plain syscall without pointers.
+            let pgid = unsafe { libc::getpgid(req.pid as libc::pid_t) };
+            if pgid > 0 {
+                kill_group(pgid);
+            }
+            resp.error = -libc::EPERM;
+        }
+        // SAFETY: `resp` matches the size encoded in the ioctl request.
+        unsafe { libc::ioctl(fd, libc::SECCOMP_IOCTL_NOTIF_SEND, &raw mut resp) };
+    }
+}
+
+/// Supervisor of the filter of a single execution. Stops once dropped.
+pub struct Seccomp {
+    /// the child's end of the socket the listener is passed over
+    child: Option<OwnedFd>,
+    stop: Arc<AtomicBool>,
+    violation: Arc<OnceLock<String>>,
+}
+
+impl Seccomp {
+    /// Makes `command` install a filter denying `denied`, and starts the supervisor. Hooks
+    /// registered earlier run unfiltered. `workdir` is where the working directory of the command
+    /// really is, see [`resolve`]. `None` if nothing can be denied, see the module docs.
+    pub fn apply(
+        command: &mut Command,
+        mut denied: Syscalls,
+        workdir: Option<&Path>,
+    ) -> io::Result<Option<Self>> {
+        static SCRIPT: AtomicBool = AtomicBool::new(false);
+        if !*SUPPORTED {
+            return Ok(None);
+        }
+        let binary = if denied.exec {
+            let binary = resolve(command, workdir).and_then(|el| fs::metadata(el).ok());
+            if binary.is_none() && !SCRIPT.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "{:?} is a script or cannot be found, executing programs is not filtered.",
+                    command.as_std().get_program()
+                );
+            }
+            denied.exec = binary.is_some();
+            binary
+        } else {
+            None
+        };
+        if !denied.any() {
+            return Ok(None);
+        }
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for both descriptors.
+        check(unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        })?;
+        // SAFETY: both descriptors were just created and are owned by nobody else.
+        let (parent, child) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        let ret = Self {
+            child: Some(child),
+            stop: Arc::new(AtomicBool::new(false)),
+            violation: Arc::new(OnceLock::new()),
+        };
+        let (stop, violation) = (ret.stop.clone(), ret.violation.clone());
+        thread::Builder::new()
+            .name("seccomp".into())
+            .spawn(move || supervise(&parent, binary.as_ref(), &stop, &violation))?;
+        let filter = program(denied);
+        let socket = fds[1];
+        // SAFETY: see `install`.
+        unsafe {
+            command.pre_exec(move || install(&filter, socket));
+        }
+        Ok(Some(ret))
+    }
+    /// Closes our copy of the child's socket, once the process is spawned.
+    pub fn started(&mut self) {
+        self.child = None;
+    }
+    /// The first denied syscall the process made.
+    pub fn violation(&self) -> Option<String> {
+        self.violation.get().cloned()
+    }
+}
+
+impl Drop for Seccomp {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
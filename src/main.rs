@@ -22,18 +22,25 @@ use log::LevelFilter;
 use log::{debug, error, info, trace, warn};
 use std::{
     collections::{HashMap, HashSet},
+    fmt::{self, Write as _},
     path::PathBuf,
 };
 use tokio::{
     fs::{File, remove_dir_all},
     io::AsyncWriteExt as _,
 };
+pub mod cases;
 pub mod checker;
+pub mod compare;
 pub mod config;
 pub mod executable;
+pub mod files;
 #[cfg(feature = "gui")]
 pub mod gui;
+pub mod interact;
+pub mod judge;
 pub mod lang;
+pub mod score;
 pub mod test;
 pub mod unpacker;
 use anyhow::Result;
@@ -151,28 +158,78 @@ async fn run() -> Result<()> {
     debug!("Results: {res:#?}");
     let mut points = vec![];
     for i in res {
-        let mut acc = 0;
-        for j in 0..i.1.len() {
-            #[expect(clippy::indexing_slicing)]
-            if i.1[j].is_correct() {
-                acc += config.testcases[j].points;
+        let score = score::score(&CONFIG.groups, &i.1);
+        // a submission that does not compile fails every case with the same errors, shown once.
+        let mut compile_errors = HashSet::new();
+        // one line per case, so students can see why they lost credit.
+        let mut line = |n: usize, el: &test::TestResult, indent: &str| {
+            let mut line = match el.case.name.as_deref() {
+                Some(name) if !el.case.hidden => format!("{indent}{name}: {el}\n"),
+                _ => format!("{indent}{}: {el}\n", n + 1),
+            };
+            // failed interactions are easier to follow with what was said.
+            if let Some(transcript) = el.transcript.as_ref()
+                && !el.is_correct()
+                && !el.case.hidden
+            {
+                for l in transcript.to_string().lines() {
+                    writeln!(line, "{indent}  {l}")?;
+                }
+            }
+            if let test::Verdict::RuntimeError {
+                trace: Some(ref trace),
+                ..
+            } = el.verdict
+                && !el.case.hidden
+            {
+                for l in trace.lines() {
+                    writeln!(line, "{indent}  {}", l.replace('\t', "  "))?;
+                }
+            }
+            if let test::Verdict::CompileError { ref reason } = el.verdict
+                && !el.case.hidden
+                && compile_errors.insert(reason.clone())
+            {
+                for l in test::shorten(reason).lines() {
+                    writeln!(line, "{indent}  {}", l.replace('\t', "  "))?;
+                }
+            }
+            Ok::<_, fmt::Error>(line)
+        };
+        let mut report =
+            i.1.iter()
+                .enumerate()
+                .filter(|el| el.1.case.group.is_none())
+                .map(|el| line(el.0, el.1, "  "))
+                .collect::<Result<String, _>>()?;
+        // grouped cases go below the score of their group.
+        for group in &score.groups {
+            writeln!(report, "  {}: {group}", group.group.name)?;
+            for (n, el) in i.1.iter().enumerate() {
+                if el.case.group.as_ref() == Some(&group.group.name) {
+                    report.push_str(&line(n, el, "    ")?);
+                }
             }
         }
         #[expect(clippy::unwrap_used)]
-        points.push((i.0.file_name().unwrap().to_str().unwrap().to_owned(), acc));
+        points.push((
+            i.0.file_name().unwrap().to_str().unwrap().to_owned(),
+            score.total,
+            report,
+        ));
     }
     if let Some(s) = SIMPLEOPTS.output.clone() {
         let mut file = File::create(s).await?;
         #[expect(clippy::expect_used)]
         for i in points {
-            file.write_all(&format!("{}: {}\n", i.0, i.1).into_bytes())
+            file.write_all(&format!("{}: {}\n{}", i.0, i.1, i.2).into_bytes())
                 .await
                 .expect("Failed to write to result file!");
         }
     } else {
         #[expect(clippy::print_stdout)]
         for i in points {
-            println!("{}: {}", i.0, i.1);
+            print!("{}: {}\n{}", i.0, i.1, i.2);
         }
     }
     #[cfg(not(feature = "gui"))]
@@ -0,0 +1,355 @@
+//! Scoring of a submission from the results of its test cases.
+//!
+//! Cases without a group earn their own points. Cases in a group, i.e. a subtask, are scored
+//! together: either the group is passed as a whole, or it earns points in proportion to its cases.
+//! A group can depend on other groups, and only earns points if all of them are passed.
+
+use crate::test::{TestCase, TestResult};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// How the points of a group follow from its cases.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Scoring {
+    /// all points if every case is accepted, none otherwise
+    #[default]
+    AllOrNothing,
+    /// the share of the points its cases earned
+    Proportional,
+}
+
+impl core::fmt::Display for Scoring {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::AllOrNothing => return write!(f, "all or nothing"),
+            Self::Proportional => return write!(f, "proportional"),
+        }
+    }
+}
+
+/// A group of test cases scored together.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Group {
+    pub name: String,
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// points of the whole group, defaults to the sum of its cases
+    pub points: Option<u64>,
+    /// groups that have to be passed for this one to count
+    #[serde(default)]
+    pub depends: Vec<String>,
+}
+
+/// Points a submission earned for one group.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct GroupScore<'a> {
+    pub group: &'a Group,
+    pub earned: u64,
+    pub max: u64,
+    /// a dependency that was not passed, so the group earned nothing
+    pub blocked: Option<&'a str>,
+    /// whether every case of the group was accepted
+    pub accepted: bool,
+}
+
+impl GroupScore<'_> {
+    /// Whether every case of the group was accepted and none of its dependencies blocked it.
+    pub const fn passed(&self) -> bool {
+        self.accepted && self.blocked.is_none()
+    }
+}
+
+impl core::fmt::Display for GroupScore<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{} ({}", self.earned, self.max, self.group.scoring)?;
+        if let Some(dependency) = self.blocked {
+            write!(f, ", needs {dependency}")?;
+        }
+        return write!(f, ")");
+    }
+}
+
+/// Fails if `name` depends on itself, with the groups on the current path in `visiting`.
+fn visit<'a>(
+    name: &'a str,
+    groups: &'a [Group],
+    visiting: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+) -> Result<()> {
+    if done.contains(name) {
+        return Ok(());
+    }
+    if visiting.contains(&name) {
+        visiting.push(name);
+        bail!("groups depend on each other: {}", visiting.join(" -> "));
+    }
+    visiting.push(name);
+    if let Some(group) = groups.iter().find(|el| el.name == name) {
+        for dependency in &group.depends {
+            visit(dependency, groups, visiting, done)?;
+        }
+    }
+    visiting.pop();
+    done.insert(name);
+    Ok(())
+}
+
+/// Checks that group names are unique, and that cases and dependencies only name existing
+/// groups without going in circles.
+pub fn validate(groups: &[Group], cases: &[TestCase]) -> Result<()> {
+    let mut names = HashSet::new();
+    for group in groups {
+        if !names.insert(group.name.as_str()) {
+            bail!("group {} is defined twice", group.name);
+        }
+    }
+    for group in groups {
+        if let Some(unknown) = group.depends.iter().find(|el| !names.contains(el.as_str())) {
+            bail!("group {} depends on unknown group {unknown}", group.name);
+        }
+    }
+    if let Some(unknown) = cases
+        .iter()
+        .filter_map(|el| el.group.as_deref())
+        .find(|el| !names.contains(el))
+    {
+        bail!("a test case belongs to unknown group {unknown}");
+    }
+    let mut done = HashSet::new();
+    for group in groups {
+        visit(&group.name, groups, &mut vec![], &mut done)?;
+    }
+    Ok(())
+}
+
+/// Points of a submission, in total and per group.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Score<'a> {
+    pub total: u64,
+    pub groups: Vec<GroupScore<'a>>,
+}
+
+/// Score of `group`, scoring its dependencies first. Memoized in `scored`.
+fn group_score<'a>(
+    group: &'a Group,
+    groups: &'a [Group],
+    results: &[TestResult],
+    scored: &mut HashMap<&'a str, GroupScore<'a>>,
+) -> GroupScore<'a> {
+    if let Some(s) = scored.get(group.name.as_str()) {
+        return s.clone();
+    }
+    let cases: Vec<_> = results
+        .iter()
+        .filter(|el| el.case.group.as_deref() == Some(group.name.as_str()))
+        .collect();
+    let earned: u64 = cases.iter().map(|el| el.points()).sum();
+    let possible: u64 = cases.iter().map(|el| el.case.points).sum();
+    let max = group.points.unwrap_or(possible);
+    let accepted = cases.iter().all(|el| el.is_correct());
+    // rounded down like every other partial score, so a failed case never earns all points.
+    let mut earned = match group.scoring {
+        Scoring::AllOrNothing if accepted => max,
+        Scoring::AllOrNothing => 0,
+        Scoring::Proportional if possible == 0 => 0,
+        Scoring::Proportional => {
+            u64::try_from(u128::from(earned) * u128::from(max) / u128::from(possible))
+                .unwrap_or(max)
+        }
+    };
+    let mut blocked = None;
+    for dependency in &group.depends {
+        let Some(dependency) = groups.iter().find(|el| &el.name == dependency) else {
+            continue;
+        };
+        if !group_score(dependency, groups, results, scored).passed() {
+            earned = 0;
+            blocked = Some(dependency.name.as_str());
+            break;
+        }
+    }
+    let s = GroupScore {
+        group,
+        earned,
+        max,
+        blocked,
+        accepted,
+    };
+    scored.insert(group.name.as_str(), s.clone());
+    s
+}
+
+/// Scores `results`, which are expected to be validated with [`validate`].
+pub fn score<'a>(groups: &'a [Group], results: &[TestResult]) -> Score<'a> {
+    let mut scored: HashMap<&str, GroupScore<'a>> = HashMap::new();
+    let groups: Vec<_> = groups
+        .iter()
+        .map(|el| group_score(el, groups, results, &mut scored))
+        .collect();
+    let ungrouped: u64 = results
+        .iter()
+        .filter(|el| el.case.group.is_none())
+        .map(TestResult::points)
+        .sum();
+    Score {
+        total: ungrouped + groups.iter().map(|el| el.earned).sum::<u64>(),
+        groups,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::Verdict;
+    use core::time::Duration;
+
+    fn group(name: &str, scoring: Scoring, depends: &[&str]) -> Group {
+        Group {
+            name: name.to_owned(),
+            scoring,
+            points: None,
+            depends: depends.iter().map(|&el| el.to_owned()).collect(),
+        }
+    }
+
+    fn case(group: Option<&str>, points: u64) -> TestCase {
+        TestCase {
+            group: group.map(str::to_owned),
+            points,
+            ..TestCase::default()
+        }
+    }
+
+    fn result(group: Option<&str>, points: u64, verdict: Verdict) -> TestResult {
+        let case = Box::leak(Box::new(case(group, points)));
+        TestResult::new(case, verdict, Duration::ZERO, None)
+    }
+
+    fn wrong() -> Verdict {
+        Verdict::WrongAnswer {
+            loc: vec![],
+            file: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn all_or_nothing_and_proportional() {
+        let groups = [
+            group("all", Scoring::AllOrNothing, &[]),
+            group("share", Scoring::Proportional, &[]),
+        ];
+        let results = [
+            result(None, 1, Verdict::Accepted),
+            result(Some("all"), 2, Verdict::Accepted),
+            result(Some("all"), 2, wrong()),
+            result(Some("share"), 2, Verdict::Accepted),
+            result(Some("share"), 2, wrong()),
+        ];
+        let score = score(&groups, &results);
+        assert_eq!((score.groups[0].earned, score.groups[0].max), (0, 4));
+        assert_eq!((score.groups[1].earned, score.groups[1].max), (2, 4));
+        assert_eq!(score.total, 3);
+    }
+
+    #[test]
+    fn partial_credit_and_group_points() {
+        let mut share = group("share", Scoring::Proportional, &[]);
+        share.points = Some(10);
+        let all = group("all", Scoring::AllOrNothing, &[]);
+        let partial = || Verdict::Partial {
+            points: 1,
+            message: String::new(),
+        };
+        let results = [
+            result(Some("share"), 2, Verdict::Accepted),
+            result(Some("share"), 2, partial()),
+            result(Some("all"), 2, Verdict::Accepted),
+            result(Some("all"), 2, partial()),
+        ];
+        let groups = [share, all];
+        let score = score(&groups, &results);
+        // 3 of 4 points scale to 7.5 of 10, rounded down.
+        assert_eq!((score.groups[0].earned, score.groups[0].max), (7, 10));
+        // partial credit is not enough to pass a group as a whole.
+        assert_eq!(score.groups[1].earned, 0);
+        assert_eq!(score.total, 7);
+    }
+
+    #[test]
+    fn proportional_rounds_down() {
+        let mut share = group("share", Scoring::Proportional, &[]);
+        share.points = Some(1);
+        let groups = [share, group("after", Scoring::AllOrNothing, &["share"])];
+        let results = [
+            result(Some("share"), 3, Verdict::Accepted),
+            result(Some("share"), 1, wrong()),
+            result(Some("after"), 1, Verdict::Accepted),
+        ];
+        let score = score(&groups, &results);
+        assert_eq!((score.groups[0].earned, score.groups[0].max), (0, 1));
+        assert!(!score.groups[0].passed());
+        assert_eq!(score.groups[1].blocked, Some("share"));
+        assert_eq!(score.total, 0);
+    }
+
+    #[test]
+    fn dependent_group_is_skipped() {
+        let groups = [
+            group("large", Scoring::Proportional, &["small"]),
+            group("small", Scoring::AllOrNothing, &[]),
+            group("other", Scoring::AllOrNothing, &[]),
+            group("after", Scoring::AllOrNothing, &["other"]),
+        ];
+        let results = [
+            result(Some("small"), 1, Verdict::Accepted),
+            result(Some("small"), 1, wrong()),
+            result(Some("large"), 5, Verdict::Accepted),
+            result(Some("other"), 1, Verdict::Accepted),
+            result(Some("after"), 3, Verdict::Accepted),
+        ];
+        let score = score(&groups, &results);
+        assert_eq!(score.groups[0].earned, 0);
+        assert_eq!(score.groups[0].blocked, Some("small"));
+        assert_eq!(
+            score.groups[0].to_string(),
+            "0/5 (proportional, needs small)"
+        );
+        assert_eq!(score.groups[3].earned, 3);
+        assert_eq!(score.groups[3].blocked, None);
+        assert_eq!(score.total, 4);
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let groups = [
+            group("a", Scoring::AllOrNothing, &["b"]),
+            group("b", Scoring::AllOrNothing, &["c"]),
+            group("c", Scoring::AllOrNothing, &["a"]),
+        ];
+        let err = validate(&groups, &[]).unwrap_err().to_string();
+        assert_eq!(err, "groups depend on each other: a -> b -> c -> a");
+        let groups = [group("a", Scoring::AllOrNothing, &["a"])];
+        assert!(validate(&groups, &[]).is_err());
+    }
+
+    #[test]
+    fn unknown_groups() {
+        let groups = [group("a", Scoring::AllOrNothing, &[])];
+        assert!(validate(&groups, &[case(Some("a"), 1), case(None, 1)]).is_ok());
+        assert!(validate(&groups, &[case(Some("b"), 1)]).is_err());
+        let groups = [group("a", Scoring::AllOrNothing, &["b"])];
+        assert!(validate(&groups, &[]).is_err());
+        let groups = [
+            group("a", Scoring::AllOrNothing, &[]),
+            group("a", Scoring::Proportional, &[]),
+        ];
+        assert!(validate(&groups, &[]).is_err());
+    }
+}
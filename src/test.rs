@@ -1,25 +1,115 @@
+use crate::compare::Compare;
 use crate::config;
 use crate::config::{CONFIG, MULTIPROG};
-use crate::executable::Language;
+use crate::files::{self, Placed, Written};
+use crate::interact::{Interactor, Transcript};
+use crate::judge::Judge;
+use crate::lang::execution::Captured;
+use crate::lang::harness::{self, Call};
 use crate::lang::runner::{self, RunError, Runner};
+use crate::lang::sandbox::Limits;
 use console::style;
 use core::{ops::Range, time::Duration};
 use imara_diff::{Algorithm, diff, intern::InternedInput};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::{debug, error, info};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
 use tokio::sync::{Mutex, MutexGuard, Semaphore};
+/// A single test case. Everything but the input and the expected output is optional, and the
+/// limits fall back to the global ones.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
 #[non_exhaustive]
 pub struct TestCase {
+    /// shown in reports instead of the number of the case
+    pub name: Option<String>,
+    pub description: Option<String>,
     pub input: String,
+    #[serde(alias = "output")]
     pub expected: String,
     pub points: u64,
+    /// command-line arguments passed to the program
+    pub args: Vec<String>,
+    /// environment variables set for the program
+    pub env: BTreeMap<String, String>,
+    /// wall-clock time limit in ms
+    pub timeout: Option<u64>,
+    /// CPU time limit in ms
+    pub cpu_timeout: Option<u64>,
+    /// memory limit in MB
+    pub memory: Option<u64>,
+    /// hidden cases only show their verdict and points in reports
+    pub hidden: bool,
+    /// how the output is compared, defaults to the global mode
+    pub compare: Option<Compare>,
+    /// checker that decides instead of comparing the output
+    pub judge: Option<Judge>,
+    /// talks to the program while it runs, instead of passing all of the input at once
+    pub interactor: Option<Interactor>,
+    /// group the case is scored in
+    pub group: Option<String>,
+    /// wrong output earns points for the share that matches
+    pub partial: bool,
+    /// files written into the working directory before the run, by relative path
+    pub files: BTreeMap<String, String>,
+    /// files the program has to write, compared like stdout
+    pub expected_files: BTreeMap<String, String>,
+    /// exit code the program has to exit with, defaults to 0
+    pub exit_code: Option<i32>,
+    /// what the program has to print to stderr
+    pub stderr: Option<String>,
+    /// how stderr is compared, defaults to the mode of the case
+    pub stderr_compare: Option<Compare>,
+    /// class of the exception the program has to die from, with or without its package
+    pub exception: Option<String>,
+    /// Java method called instead of running `main`
+    pub call: Option<Call>,
+    /// position among all cases of the config, set once they are loaded
+    #[serde(skip)]
+    pub index: usize,
+}
+impl Default for TestCase {
+    fn default() -> Self {
+        return Self {
+            name: None,
+            description: None,
+            input: String::new(),
+            expected: String::new(),
+            points: 1,
+            args: vec![],
+            env: BTreeMap::new(),
+            timeout: None,
+            cpu_timeout: None,
+            memory: None,
+            hidden: false,
+            compare: None,
+            judge: None,
+            interactor: None,
+            group: None,
+            partial: false,
+            files: BTreeMap::new(),
+            expected_files: BTreeMap::new(),
+            exit_code: None,
+            stderr: None,
+            stderr_compare: None,
+            exception: None,
+            call: None,
+            index: 0,
+        };
+    }
 }
 impl core::fmt::Display for TestCase {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(name) = &self.name {
+            writeln!(f, "Name: {name}")?;
+        }
+        if let Some(description) = &self.description {
+            writeln!(f, "Description: {description}")?;
+        }
         return write!(
             f,
             "Input: {}\nExpected Output: {}\nPoints: {}",
@@ -29,6 +119,18 @@ impl core::fmt::Display for TestCase {
 }
 
 impl TestCase {
+    /// Whether the case reads or writes files besides stdin and stdout.
+    pub fn has_files(&self) -> bool {
+        return !self.files.is_empty() || !self.expected_files.is_empty();
+    }
+    /// How the output of this case is compared.
+    pub fn compare(&self) -> Compare {
+        return self.compare.unwrap_or(CONFIG.compare);
+    }
+    /// How stderr of this case is compared.
+    pub fn stderr_compare(&self) -> Compare {
+        return self.stderr_compare.unwrap_or_else(|| self.compare());
+    }
     #[expect(unused)]
     fn diff<'a>(
         &'a self,
@@ -64,49 +166,420 @@ impl TestCase {
     }
 }
 
+/// Why a test case passed or failed.
 #[derive(Debug, Clone)]
 #[non_exhaustive]
-pub enum TestResult {
-    Correct {
-        case: &'static TestCase,
-    },
-    Wrong {
-        case: &'static TestCase,
+pub enum Verdict {
+    Accepted,
+    WrongAnswer {
         loc: Vec<WrongLine<usize>>,
+        /// output file or `stderr` that is wrong, `None` for stdout. `loc` is empty if the file
+        /// was not written.
+        file: Option<String>,
+        /// why the run is wrong if not for its output, e.g. an unexpected exit code
+        reason: Option<String>,
     },
-    Error {
+    TimeLimit {
+        /// whether the CPU time limit was hit, rather than the wall-clock one
+        cpu: bool,
+    },
+    MemoryLimit,
+    RuntimeError {
+        /// exit code, `None` if the process was killed by a signal
+        code: Option<i32>,
+        /// class of the uncaught exception, if the runtime reported one
+        exception: Option<String>,
+        /// stack trace of the uncaught exception, shortened to [`TRACE_LINES`] lines
+        trace: Option<String>,
+        stderr: String,
+    },
+    OutputLimit {
+        /// `stdout` or `stderr`
+        stream: &'static str,
+    },
+    /// killed for making a syscall the `allow` list does not allow
+    SecurityViolation {
+        syscall: String,
+    },
+    CompileError {
+        /// what the compiler printed, shortened with [`shorten`] in reports
+        reason: String,
+    },
+    /// the checker gave part of the points
+    Partial {
+        points: u64,
+        message: String,
+    },
+    /// the checker itself failed, so the output could not be judged
+    JudgeError {
         reason: String,
-        code: i32,
     },
 }
 
+impl Verdict {
+    /// Two-letter tag used in logs and reports.
+    pub const fn tag(&self) -> &'static str {
+        match self {
+            Self::Accepted => return "AC",
+            Self::WrongAnswer { .. } => return "WA",
+            Self::TimeLimit { .. } => return "TL",
+            Self::MemoryLimit => return "ML",
+            Self::RuntimeError { .. } => return "RE",
+            Self::OutputLimit { .. } => return "OL",
+            Self::SecurityViolation { .. } => return "SV",
+            Self::CompileError { .. } => return "CE",
+            Self::Partial { .. } => return "PA",
+            Self::JudgeError { .. } => return "JE",
+        }
+    }
+}
+
+impl core::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Accepted => return write!(f, "Accepted"),
+            Self::WrongAnswer {
+                reason: Some(reason),
+                ..
+            } => return write!(f, "Wrong answer ({reason})"),
+            Self::WrongAnswer {
+                file: Some(file),
+                loc,
+                ..
+            } => match loc.first() {
+                Some(l) => {
+                    return write!(f, "Wrong answer ({file} from line {})", l.before.start + 1);
+                }
+                None => return write!(f, "Wrong answer ({file} is missing)"),
+            },
+            Self::WrongAnswer {
+                loc, file: None, ..
+            } => match loc.first() {
+                Some(l) => return write!(f, "Wrong answer (from line {})", l.before.start + 1),
+                None => return write!(f, "Wrong answer"),
+            },
+            Self::TimeLimit { cpu: true } => return write!(f, "Time limit exceeded (CPU)"),
+            Self::TimeLimit { cpu: false } => return write!(f, "Time limit exceeded (wall)"),
+            Self::MemoryLimit => return write!(f, "Memory limit exceeded"),
+            Self::RuntimeError {
+                exception: Some(e), ..
+            } => return write!(f, "Runtime error: {e}"),
+            Self::RuntimeError { code: Some(c), .. } => {
+                return write!(f, "Runtime error (exit code {c})");
+            }
+            Self::RuntimeError { .. } => return write!(f, "Runtime error (killed by signal)"),
+            Self::OutputLimit { stream } => return write!(f, "Output limit exceeded ({stream})"),
+            Self::SecurityViolation { syscall } => {
+                return write!(f, "Forbidden syscall: {syscall}");
+            }
+            Self::CompileError { .. } => return write!(f, "Compile error"),
+            Self::Partial { message, .. } if message.is_empty() => {
+                return write!(f, "Partially accepted");
+            }
+            Self::Partial { message, .. } => return write!(f, "Partially accepted: {message}"),
+            Self::JudgeError { reason } => return write!(f, "Checker failed: {reason}"),
+        }
+    }
+}
+
+/// Outcome of running one test case.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TestResult {
+    pub case: &'static TestCase,
+    pub verdict: Verdict,
+    /// wall-clock time of the run
+    pub runtime: Duration,
+    /// CPU time of the run, if it could be measured
+    pub cpu: Option<Duration>,
+    /// peak memory in KB, if it could be measured
+    pub memory: Option<u64>,
+    /// what the program printed, up to the output limit
+    pub stdout: Captured,
+    pub stderr: Captured,
+    /// what was exchanged with the program, for interactive cases
+    pub transcript: Option<Transcript>,
+}
+
 impl TestResult {
+    pub const fn new(
+        case: &'static TestCase,
+        verdict: Verdict,
+        runtime: Duration,
+        memory: Option<u64>,
+    ) -> Self {
+        return Self {
+            case,
+            verdict,
+            runtime,
+            cpu: None,
+            memory,
+            stdout: Captured::new(),
+            stderr: Captured::new(),
+            transcript: None,
+        };
+    }
+    #[must_use]
+    pub fn with_cpu_time(self, cpu: Option<Duration>) -> Self {
+        return Self { cpu, ..self };
+    }
+    /// Keeps the output of the run.
+    #[must_use]
+    pub fn with_output(self, stdout: Captured, stderr: Captured) -> Self {
+        return Self {
+            stdout,
+            stderr,
+            ..self
+        };
+    }
+    #[must_use]
+    pub fn with_transcript(self, transcript: Option<Transcript>) -> Self {
+        return Self { transcript, ..self };
+    }
     pub const fn is_correct(&self) -> bool {
-        match self {
-            Self::Correct { .. } => return true,
-            Self::Wrong { .. } | Self::Error { .. } => return false,
+        return matches!(self.verdict, Verdict::Accepted);
+    }
+    /// Points earned for this case.
+    pub const fn points(&self) -> u64 {
+        if self.is_correct() {
+            return self.case.points;
         }
+        if let Verdict::Partial { points, .. } = self.verdict {
+            return points;
+        }
+        return 0;
     }
     pub const fn get_loc(&self) -> Option<&Vec<WrongLine<usize>>> {
-        match &self {
-            Self::Wrong { loc, .. } => return Some(loc),
-            Self::Correct { .. } | Self::Error { .. } => return None,
+        match &self.verdict {
+            Verdict::WrongAnswer { loc, .. } => return Some(loc),
+            _ => return None,
         }
     }
     #[must_use]
     pub fn msg(&self) -> String {
-        if self.is_correct() {
-            return style("[AC]").green().bold().to_string();
+        let tag = style(format!("[{}]", self.verdict.tag())).bold();
+        match self.verdict {
+            Verdict::Accepted => return tag.green().to_string(),
+            Verdict::CompileError { .. }
+            | Verdict::TimeLimit { .. }
+            | Verdict::MemoryLimit
+            | Verdict::OutputLimit { .. }
+            | Verdict::Partial { .. }
+            | Verdict::JudgeError { .. } => return tag.yellow().to_string(),
+            _ => return tag.red().to_string(),
+        }
+    }
+}
+
+/// One report line, e.g. `[WA] 0/2 Wrong answer (from line 3) 0.12s (CPU 0.10s) 9MB`.
+impl core::fmt::Display for TestResult {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // hidden cases only tell whether they passed.
+        if self.case.hidden {
+            return write!(
+                f,
+                "[{}] {}/{} (hidden)",
+                self.verdict.tag(),
+                self.points(),
+                self.case.points
+            );
+        }
+        write!(
+            f,
+            "[{}] {}/{} ",
+            self.verdict.tag(),
+            self.points(),
+            self.case.points
+        )?;
+        // the comparison mode decided these, so it is part of the verdict.
+        let mode = match (&self.case.interactor, &self.case.judge, &self.case.call) {
+            (Some(_), ..) => "interactive".to_owned(),
+            (None, Some(judge), _) => judge.to_string(),
+            (None, None, Some(call)) => format!("calls {}", call.method),
+            (None, None, None) => self.case.compare().to_string(),
+        };
+        let failure = self.transcript.as_ref().and_then(|el| el.failure.as_ref());
+        match &self.verdict {
+            Verdict::Accepted => write!(f, "Accepted ({mode})")?,
+            Verdict::WrongAnswer {
+                reason: Some(reason),
+                ..
+            } => write!(f, "Wrong answer ({reason})")?,
+            Verdict::WrongAnswer { .. } if let Some(reason) = failure => {
+                write!(f, "Wrong answer ({reason})")?;
+            }
+            Verdict::WrongAnswer {
+                loc,
+                file: Some(file),
+                ..
+            } => match loc.first() {
+                Some(l) if file == "stderr" => write!(
+                    f,
+                    "Wrong answer (stderr from line {}, {})",
+                    l.before.start + 1,
+                    self.case.stderr_compare()
+                )?,
+                Some(l) => write!(
+                    f,
+                    "Wrong answer ({file} from line {}, {mode})",
+                    l.before.start + 1
+                )?,
+                None => write!(f, "Wrong answer ({file} is missing)")?,
+            },
+            Verdict::WrongAnswer {
+                loc, file: None, ..
+            } => match loc.first() {
+                Some(l) => write!(f, "Wrong answer (from line {}, {mode})", l.before.start + 1)?,
+                None => write!(f, "Wrong answer ({mode})")?,
+            },
+            v => write!(f, "{v}")?,
+        }
+        write!(f, " {:.2}s", self.runtime.as_secs_f64())?;
+        if let Some(c) = self.cpu {
+            write!(f, " (CPU {:.2}s)", c.as_secs_f64())?;
+        }
+        if let Some(m) = self.memory {
+            write!(f, " {}MB", m.div_ceil(1024))?;
+        }
+        return Ok(());
+    }
+}
+
+/// Lines of a stack trace or of compiler errors shown in reports.
+const TRACE_LINES: usize = 20;
+
+/// `text` cut after [`TRACE_LINES`] lines, with the number of lines left out.
+#[must_use]
+pub fn shorten(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let short = lines[..lines.len().min(TRACE_LINES)].join("\n");
+    if lines.len() > TRACE_LINES {
+        return format!("{short}\n... {} more lines", lines.len() - TRACE_LINES);
+    }
+    return short;
+}
+
+/// Class of the uncaught exception or panic reported on stderr, for Java, Python, C++ and Rust.
+fn exception_class(stderr: &str) -> Option<String> {
+    static PATTERNS: LazyLock<[Regex; 3]> = LazyLock::new(|| {
+        [
+            Regex::new(r#"Exception in thread "[^"]*" ([\w.$]+)"#).unwrap(),
+            Regex::new(r"terminate called after throwing an instance of '([^']+)'").unwrap(),
+            Regex::new(r"(?m)^thread '[^']*' panicked at").unwrap(),
+        ]
+    });
+    static PYTHON: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?m)^([A-Za-z_][\w.]*)(?::|$)").unwrap());
+    for re in PATTERNS.iter() {
+        if let Some(c) = re.captures(stderr) {
+            return Some(c.get(1).map_or("panic", |el| el.as_str()).to_owned());
         }
-        return style("[NG]").red().bold().to_string();
+    }
+    // python prints the exception as the last line of the traceback.
+    let (_, trace) = stderr.split_once("Traceback (most recent call last):")?;
+    trace
+        .lines()
+        .filter(|l| !l.starts_with(' '))
+        .filter_map(|l| PYTHON.captures(l))
+        .last()
+        .map(|c| c.get(1).unwrap().as_str().to_owned())
+}
+
+/// Stack trace of the uncaught exception on stderr, for Java and Python. Long traces, e.g. of a
+/// stack overflow, are cut after [`TRACE_LINES`] lines.
+fn stack_trace(stderr: &str) -> Option<String> {
+    let lines: Vec<&str> = stderr.lines().collect();
+    let (start, end) = if let Some(start) = lines
+        .iter()
+        .position(|l| l.starts_with("Exception in thread \""))
+    {
+        let len = lines[start + 1..]
+            .iter()
+            .take_while(|l| l.starts_with('\t') || l.starts_with("Caused by:"))
+            .count();
+        (start, start + 1 + len)
+    } else {
+        // chained exceptions print one traceback each, the last one is uncaught.
+        let start = lines
+            .iter()
+            .rposition(|l| l.starts_with("Traceback (most recent call last):"))?;
+        // the frames are indented, and the exception follows them.
+        let len = lines[start + 1..]
+            .iter()
+            .take_while(|l| l.starts_with(' '))
+            .count();
+        (start, (start + 2 + len).min(lines.len()))
+    };
+    Some(shorten(&lines[start..end].join("\n")))
+}
+
+/// Whether `class` is `expected`, which may leave out the package, e.g. `IllegalStateException`
+/// for `java.lang.IllegalStateException`.
+fn same_class(class: &str, expected: &str) -> bool {
+    class == expected
+        || class
+            .strip_suffix(expected)
+            .is_some_and(|el| el.ends_with(['.', '$']))
+}
+
+/// Verdict on how the program exited, `None` if it exited as the case expects.
+fn exit_verdict(
+    testcase: &TestCase,
+    status: Option<std::process::ExitStatus>,
+    stderr: &str,
+) -> Option<Verdict> {
+    let code = status.and_then(|el| el.code());
+    let success = status.is_none_or(|el| el.success());
+    // a Java thread other than main can die without failing the process.
+    let exception =
+        exception_class(stderr).filter(|_| !success || stderr.contains("Exception in thread \""));
+    let crashed = || {
+        return Some(Verdict::RuntimeError {
+            code,
+            exception: exception.clone(),
+            trace: stack_trace(stderr),
+            stderr: stderr.to_owned(),
+        });
+    };
+    let wrong = |reason: String| {
+        return Some(Verdict::WrongAnswer {
+            loc: vec![],
+            file: None,
+            reason: Some(reason),
+        });
+    };
+    if let Some(expected) = &testcase.exception {
+        match &exception {
+            Some(class) if same_class(class, expected) => return None,
+            Some(_) => return crashed(),
+            None if !success => return crashed(),
+            None => return wrong(format!("{expected} was not thrown")),
+        }
+    }
+    if exception.is_some() {
+        return crashed();
+    }
+    match (testcase.exit_code, code) {
+        (Some(expected), Some(c)) if c == expected => return None,
+        (Some(expected), Some(c)) => return wrong(format!("exit code {c}, expected {expected}")),
+        _ if success => return None,
+        _ => return crashed(),
     }
 }
 
-#[expect(unused)]
 #[derive(Debug, Clone)]
 pub struct WrongLine<T> {
-    before: Range<T>,
-    after: (Range<T>, String),
+    pub(crate) before: Range<T>,
+    pub(crate) after: (Range<T>, String),
+}
+
+impl<T> WrongLine<T> {
+    pub(crate) const fn new(before: Range<T>, after: Range<T>, got: String) -> Self {
+        return Self {
+            before,
+            after: (after, got),
+        };
+    }
 }
 
 pub async fn test_dirs<T: IntoIterator<Item = PathBuf>>(p: T) -> Vec<(PathBuf, Vec<TestResult>)> {
@@ -144,26 +617,25 @@ pub async fn test_dirs<T: IntoIterator<Item = PathBuf>>(p: T) -> Vec<(PathBuf, V
     for i in handles {
         let out = i.await.unwrap();
         match out.1 {
-            Err(RunError::RE(code, reason)) => ret.push((
-                out.0,
-                vec![
-                    TestResult::Error {
-                        reason,
-                        code: code.unwrap()
-                    };
-                    CONFIG.testcases.len()
-                ],
-            )),
-            Err(RunError::CE(code, reason)) => ret.push((
-                out.0,
-                vec![
-                    TestResult::Error {
-                        reason,
-                        code: code.unwrap()
-                    };
-                    CONFIG.testcases.len()
-                ],
-            )),
+            Err(e) => {
+                let verdict = match e {
+                    RunError::CE(_, reason) => Verdict::CompileError { reason },
+                    RunError::RE(code, stderr) => Verdict::RuntimeError {
+                        code,
+                        exception: None,
+                        trace: None,
+                        stderr,
+                    },
+                };
+                ret.push((
+                    out.0,
+                    CONFIG
+                        .testcases
+                        .iter()
+                        .map(|el| TestResult::new(el, verdict.clone(), Duration::ZERO, None))
+                        .collect(),
+                ));
+            }
             Ok(ok) => {
                 ret.push((out.0, ok));
             }
@@ -214,7 +686,7 @@ pub async fn test_file_progress(
             .unwrap()
             .tick_strings(&config::SPINNER),
     );
-    let mut proc = match runner::from_dir(path.clone(), Some(Language::Java)).await {
+    let mut proc = match runner::from_dir(path.clone(), Some(CONFIG.lang.clone())).await {
         Some(s) => s,
         None => return (path, Err(RunError::CE(None, "Unknown".into()))),
     };
@@ -223,7 +695,7 @@ pub async fn test_file_progress(
     let filenamestr = filename.to_str().unwrap().to_owned();
     progress.set_message(filenamestr);
     progress.enable_steady_tick(Duration::from_millis(100));
-    match proc.prepare().await {
+    let compilation = match proc.compile().await {
         Err(e) => {
             info!(
                 "{} {} Compile failed!",
@@ -233,13 +705,14 @@ pub async fn test_file_progress(
             debug!("{e:#?}");
             return (path, Err(e));
         }
-        Ok(()) => {}
-    }
+        Ok(c) => c,
+    };
     progress.finish_and_clear();
     info!(
-        "{} {} Compiled successfully!",
+        "{} {} Compiled successfully in {:.2}s!",
         style("[OK]").green().bold(),
-        path.to_str().unwrap()
+        path.to_str().unwrap(),
+        compilation.time.as_secs_f64()
     );
     let progress = mp.add(ProgressBar::new(CONFIG.testcases.len() as u64));
     progress.set_style(
@@ -256,7 +729,7 @@ pub async fn test_file_progress(
     let mut ret = vec![];
     let mut correct = 0;
     for i in 0..tc.len() {
-        let push = test_proc(path.clone(), &mut proc, &tc[i]).await;
+        let push = test_proc(path.clone(), proc.as_ref(), &tc[i]).await;
         if push.is_correct() {
             correct += 1;
         }
@@ -287,61 +760,319 @@ pub async fn test_file_progress(
 
 pub async fn test_proc(
     path: PathBuf,
-    proc: &mut Box<dyn Runner>,
+    proc: &dyn Runner,
     testcase: &'static TestCase,
 ) -> TestResult {
-    let timeout = config::get_config().unwrap().timeout;
-    let mut wrong = vec![];
-    proc.run().await.unwrap();
-    let _ = proc.read_all();
-    proc.stdin(testcase.input.clone())
-        .await
-        .unwrap_or_else(|e| {
-            error!(
-                "failed to input stdin for process: {}",
-                &path.to_string_lossy()
-            );
-            error!("Reason: {e}")
-        });
-    while proc.running().await {
-        if proc.runtime().await.unwrap() > Duration::from_millis(timeout) {
-            info!(
-                "{} has been running for too long. Killing process...",
-                path.file_name().unwrap().to_str().unwrap()
-            );
-            #[cfg(unix)]
-            match proc.signal(nix::sys::signal::Signal::SIGKILL).await {
-                Err(e) => error!("failed to kill process: {e}"),
-                Ok(()) => {}
+    let limits = Limits::for_case(testcase);
+    // files are placed in the venv, which isolated runs copy when they start.
+    let workdir = testcase
+        .has_files()
+        .then(|| proc.workdir(&limits))
+        .flatten();
+    let mut placed = Placed::default();
+    if let Some(dir) = &workdir
+        && let Err(e) = files::place(dir, testcase, &mut placed).await
+    {
+        error!("failed to place the files of a case: {e}");
+        files::clean(&placed).await;
+        let verdict = Verdict::RuntimeError {
+            code: None,
+            exception: None,
+            trace: None,
+            stderr: format!("failed to place files: {e}"),
+        };
+        return TestResult::new(testcase, verdict, Duration::ZERO, None);
+    }
+    let mut exec = match proc.run(&limits, testcase).await {
+        Ok(e) => e,
+        Err(e) => {
+            files::clean(&placed).await;
+            // e.g. the generated class of a method-level case did not compile.
+            if let RunError::CE(_, reason) = e {
+                info!(
+                    "{} {} cannot run a test case.",
+                    style("[CE]").bold().yellow(),
+                    path.to_string_lossy()
+                );
+                debug!("{reason}");
+                let verdict = Verdict::CompileError { reason };
+                return TestResult::new(testcase, verdict, Duration::ZERO, None);
             }
-            while !proc.running().await {}
-            return TestResult::Error {
-                code: 9,
-                reason: "Timed out.".into(),
+            error!("failed to start process: {}", &path.to_string_lossy());
+            let verdict = Verdict::RuntimeError {
+                code: None,
+                exception: None,
+                trace: None,
+                stderr: format!("{e:?}"),
             };
+            return TestResult::new(testcase, verdict, Duration::ZERO, None);
+        }
+    };
+    let wall = limits.wall.unwrap_or(Duration::MAX);
+    let mut transcript = match &testcase.interactor {
+        Some(interactor) => Some(interactor.interact(&mut exec, testcase, wall).await),
+        None => {
+            exec.stdin(testcase.input.clone())
+                .await
+                .unwrap_or_else(|e| {
+                    error!(
+                        "failed to input stdin for process: {}",
+                        &path.to_string_lossy()
+                    );
+                    error!("Reason: {e}")
+                });
+            None
         }
+    };
+    let finished = match exec.wait_timeout(wall).await {
+        Ok(finished) => finished,
+        Err(e) => {
+            error!("failed to wait for process: {e}");
+            true
+        }
+    };
+    let out = exec.read_all().await.unwrap();
+    let err = exec.read_stderr().await;
+    let (stdout, stderr) = (out.text(), err.text());
+    let written = match exec.workdir() {
+        Some(dir) if testcase.has_files() => files::collect(dir, testcase).await,
+        _ => Written::new(),
+    };
+    files::clean(&placed).await;
+    if let Some(transcript) = &mut transcript {
+        transcript.finish(&out.data);
     }
-    let out = proc.read_all().await.unwrap();
-    let input = InternedInput::new(testcase.expected.as_str(), out.as_str());
-    let sink = |before: Range<u32>, after: Range<u32>| {
-        let hunk_after: Vec<_> = input.after[after.start as usize..after.end as usize]
-            .iter()
-            .map(|&line| return input.interner[line])
-            .collect();
-        wrong.push(WrongLine::<usize> {
-            before: before.start as usize..before.end as usize,
-            after: (
-                after.start as usize..after.end as usize,
-                hunk_after.join("\n").clone(),
-            ),
-        });
+    let (runtime, cpu, memory) = (exec.runtime(), exec.cpu_time(), exec.peak_memory());
+    let result = |verdict| {
+        TestResult::new(testcase, verdict, runtime, memory)
+            .with_cpu_time(cpu)
+            .with_output(out.clone(), err.clone())
+            .with_transcript(transcript.clone())
     };
-    imara_diff::diff(Algorithm::Histogram, &input, sink);
+    // an interaction that failed was cut short by us, which is not the program's fault.
+    let interrupted = transcript.as_ref().is_some_and(|el| el.failure.is_some()) && exec.killed();
+    let over_cpu = limits
+        .cpu
+        .is_some_and(|limit| cpu.is_some_and(|el| el > limit));
+    if !finished {
+        info!(
+            "{} has been running for too long. Killed its process group.",
+            path.file_name().unwrap().to_str().unwrap()
+        );
+        return result(Verdict::TimeLimit { cpu: over_cpu });
+    }
+    if let Some(syscall) = exec.violation() {
+        info!(
+            "{} {} made a forbidden syscall: {syscall}",
+            style("[SV]").bold().red(),
+            path.file_name().unwrap().to_str().unwrap()
+        );
+        return result(Verdict::SecurityViolation { syscall });
+    }
+    if let Some(stream) = exec.exceeded_output() {
+        info!(
+            "{} {} exceeded the output limit on {stream}.",
+            style("[OL]").bold().yellow(),
+            path.file_name().unwrap().to_str().unwrap()
+        );
+        return result(Verdict::OutputLimit { stream });
+    }
+    let status = exec.exitcode();
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        // the CPU rlimit is a backstop for the measured CPU time, so hitting it is a time limit
+        // too. Checked before the memory limit, since its hard limit is a SIGKILL.
+        let signal = status.and_then(|el| el.signal());
+        if signal == Some(nix::sys::signal::Signal::SIGXCPU as i32) || over_cpu {
+            return result(Verdict::TimeLimit { cpu: true });
+        }
+    }
+    #[cfg(not(unix))]
+    if over_cpu {
+        return result(Verdict::TimeLimit { cpu: true });
+    }
+    if exec.exceeded_memory(&stderr) {
+        info!(
+            "{} {} exceeded the memory limit.",
+            style("[ML]").bold().yellow(),
+            path.file_name().unwrap().to_str().unwrap()
+        );
+        return result(Verdict::MemoryLimit);
+    }
+    if !interrupted && let Some(verdict) = exit_verdict(testcase, status, &stderr) {
+        return result(verdict);
+    }
+    let verdict = judge_output(testcase, transcript.as_ref(), &stdout).await;
+    if !matches!(verdict, Verdict::Accepted | Verdict::Partial { .. }) {
+        return result(verdict);
+    }
+    // stderr and output files only matter once stdout earned something.
+    if let Some(expected) = &testcase.stderr {
+        let wrong = testcase.stderr_compare().check(expected, &stderr);
+        if !wrong.is_empty() {
+            return result(Verdict::WrongAnswer {
+                loc: wrong,
+                file: Some("stderr".to_owned()),
+                reason: None,
+            });
+        }
+    }
+    if let Some(wrong) = files::check(testcase, &written) {
+        return result(wrong);
+    }
+    return result(verdict);
+}
+
+/// Verdict on what the program printed to stdout, or on its interaction.
+async fn judge_output(
+    testcase: &'static TestCase,
+    transcript: Option<&Transcript>,
+    stdout: &str,
+) -> Verdict {
+    if let Some(transcript) = transcript {
+        if transcript.failure.is_some() {
+            return Verdict::WrongAnswer {
+                loc: vec![],
+                file: None,
+                reason: None,
+            };
+        }
+        return transcript.verdict.clone().unwrap_or(Verdict::Accepted);
+    }
+    if let Some(judge) = &testcase.judge {
+        return judge.check(testcase, stdout).await;
+    }
+    if testcase.call.is_some() {
+        return harness::check(testcase, stdout);
+    }
+    let wrong = testcase.compare().check(&testcase.expected, stdout);
+    if !wrong.is_empty() && testcase.partial {
+        let matched = testcase
+            .compare()
+            .matched(&testcase.expected, stdout)
+            .unwrap_or(0.0);
+        // rounded down, so only accepted output earns all points.
+        let points =
+            ((matched * testcase.points as f64) as u64).min(testcase.points.saturating_sub(1));
+        if points > 0 {
+            return Verdict::Partial {
+                points,
+                message: format!("{:.0}% of the output matches", matched * 100.0),
+            };
+        }
+    }
     if !wrong.is_empty() {
-        return TestResult::Wrong {
-            case: testcase,
+        return Verdict::WrongAnswer {
             loc: wrong,
+            file: None,
+            reason: None,
         };
     }
-    return TestResult::Correct { case: testcase };
+    return Verdict::Accepted;
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt as _;
+    use std::process::ExitStatus;
+
+    const CHAINED: &str = "Exception in thread \"main\" java.lang.IllegalStateException: outer
+\tat Main.run(Main.java:9)
+\tat Main.main(Main.java:4)
+Caused by: java.lang.IllegalArgumentException: inner
+\tat Main.parse(Main.java:14)
+\t... 2 more
+";
+
+    fn exited(code: i32) -> Option<ExitStatus> {
+        Some(ExitStatus::from_raw(code << 8))
+    }
+
+    fn expecting(exception: Option<&str>, exit_code: Option<i32>) -> TestCase {
+        TestCase {
+            exception: exception.map(str::to_owned),
+            exit_code,
+            ..TestCase::default()
+        }
+    }
+
+    #[test]
+    fn class_names() {
+        let class = "java.lang.IllegalStateException";
+        assert!(same_class(class, "IllegalStateException"));
+        assert!(same_class(class, class));
+        assert!(same_class("Main$Custom", "Custom"));
+        assert!(!same_class(class, "StateException"));
+        assert!(!same_class(class, "java.lang.IllegalArgumentException"));
+    }
+
+    #[test]
+    fn caused_by_chain() {
+        // the thrown exception is the outer one, and its causes belong to the trace.
+        assert_eq!(
+            exception_class(CHAINED).as_deref(),
+            Some("java.lang.IllegalStateException")
+        );
+        let trace = stack_trace(&format!("{CHAINED}some later output\n")).unwrap();
+        assert_eq!(trace.lines().count(), 6);
+        assert!(trace.contains("Caused by: java.lang.IllegalArgumentException: inner"));
+        let python = "Traceback (most recent call last):\n  File \"main.py\", line 1, in <module>\n    int(\"x\")\nValueError: invalid literal\n";
+        assert_eq!(exception_class(python).as_deref(), Some("ValueError"));
+    }
+
+    #[test]
+    fn expected_exception() {
+        let case = expecting(Some("IllegalStateException"), None);
+        assert!(exit_verdict(&case, exited(1), CHAINED).is_none());
+        let case = expecting(Some("java.lang.IllegalStateException"), None);
+        assert!(exit_verdict(&case, exited(1), CHAINED).is_none());
+    }
+
+    #[test]
+    fn expected_exception_not_thrown() {
+        let case = expecting(Some("IllegalStateException"), None);
+        assert!(matches!(
+            exit_verdict(&case, exited(0), ""),
+            Some(Verdict::WrongAnswer { reason: Some(ref reason), .. })
+                if reason == "IllegalStateException was not thrown"
+        ));
+    }
+
+    #[test]
+    fn other_exception_thrown() {
+        let case = expecting(Some("IllegalArgumentException"), None);
+        assert!(matches!(
+            exit_verdict(&case, exited(1), CHAINED),
+            Some(Verdict::RuntimeError { exception: Some(ref class), trace: Some(_), .. })
+                if class == "java.lang.IllegalStateException"
+        ));
+        // without an expected exception, any uncaught one is a runtime error.
+        assert!(matches!(
+            exit_verdict(&TestCase::default(), exited(1), CHAINED),
+            Some(Verdict::RuntimeError { .. })
+        ));
+    }
+
+    #[test]
+    fn exit_codes() {
+        let case = expecting(None, Some(3));
+        assert!(exit_verdict(&case, exited(3), "").is_none());
+        assert!(matches!(
+            exit_verdict(&case, exited(0), ""),
+            Some(Verdict::WrongAnswer { reason: Some(ref reason), .. })
+                if reason == "exit code 0, expected 3"
+        ));
+        assert!(exit_verdict(&TestCase::default(), exited(0), "").is_none());
+        assert!(matches!(
+            exit_verdict(&TestCase::default(), exited(2), ""),
+            Some(Verdict::RuntimeError { code: Some(2), .. })
+        ));
+        // a signal has no exit code to match.
+        assert!(matches!(
+            exit_verdict(&case, Some(ExitStatus::from_raw(9)), ""),
+            Some(Verdict::RuntimeError { code: None, .. })
+        ));
+    }
 }
@@ -1,5 +1,5 @@
 use crate::config::Orderby;
-use crate::config::{CONFIG, KNOWN_EXTENSIONS, MULTIPROG, TEMPDIR, generate_regex};
+use crate::config::{CONFIG, MULTIPROG, TEMPDIR, generate_regex, is_known_extension};
 use core::time::Duration;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, warn};
@@ -137,7 +137,7 @@ pub async fn unpack(p: PathBuf) -> Result<PathBuf, UnpackError> {
         warn!("Unpacker does not know what to do with unpacked directory! Leaving it untouched!");
         return Err(UnpackError::Ignore);
     }
-    if p.is_file() && !KNOWN_EXTENSIONS.contains(&p.extension().unwrap().to_str().unwrap()) {
+    if p.is_file() && !is_known_extension(p.extension().unwrap().to_str().unwrap()) {
         debug!("Ignoring unknown file.");
         return Err(UnpackError::Ignore);
     }